@@ -0,0 +1,168 @@
+// Errors and warnings with a source excerpt under them:
+//
+//   error[E0001]: Expected semicolon
+//    --> prog.rsm:3:9
+//     |
+//   3 | MOV A, 5
+//     |         ^
+//     = in macro PRINT called at prog.rsm:12:1
+//
+// Sources are registered as they are read so the offending line can be quoted. While quiet is set
+// diagnostics are only counted - the label pass uses it because the final pass reports the same
+// problems again. Only the first error of a statement is printed, the rest are usually fallout.
+//
+// E0001 expected semicolon          E0009 program layout (regions, banks, ROM and RAM size)
+// E0002 expected comma              E0010 macro definition or call
+// E0003 other syntax                E0011 .if/.else/.endif nesting
+// E0004 invalid operand             E0012 include files
+// E0005 invalid or oversized value  E0013 relocatable objects
+// E0006 undefined symbol            E0014 unknown instruction
+// E0007 duplicate definition        E0015 unterminated string
+// E0008 branch target out of reach
+// W0001 unknown directive, ignored  W0002 unreachable instruction
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::Token;
+
+struct State {
+    sources: HashMap<String, Vec<String>>,
+    // every error raised, printed or not - the passes compare it to notice a failed statement
+    raised: usize,
+    errors: usize,
+    warnings: usize,
+    quiet: bool,
+    statement_failed: bool,
+    last_shown: bool,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State {
+        sources: HashMap::new(),
+        raised: 0,
+        errors: 0,
+        warnings: 0,
+        quiet: false,
+        statement_failed: false,
+        last_shown: false,
+    });
+}
+
+pub fn add_source(file: &str, text: &str) {
+    STATE.with(|s| s.borrow_mut().sources.insert(file.to_string(), text.lines().map(|l| l.to_string()).collect()));
+}
+
+pub fn set_quiet(quiet: bool) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.quiet = quiet;
+        s.statement_failed = false;
+    });
+}
+
+// called at the first token of every statement
+pub fn begin_statement() {
+    STATE.with(|s| s.borrow_mut().statement_failed = false);
+}
+
+pub fn errors_raised() -> usize {
+    STATE.with(|s| s.borrow().raised)
+}
+
+pub fn error_count() -> usize {
+    STATE.with(|s| s.borrow().errors)
+}
+
+pub fn warning_count() -> usize {
+    STATE.with(|s| s.borrow().warnings)
+}
+
+fn location(t: &Token) -> String {
+    position(&t.file, t.line, t.column)
+}
+
+fn position(file: &str, line: u16, column: u16) -> String {
+    if file.is_empty() {
+        format!("line {}", line + 1)
+    } else {
+        format!("{}:{}:{}", file, line + 1, column + 1)
+    }
+}
+
+fn excerpt(t: &Token) {
+    let line: Option<String> = STATE.with(|s| s.borrow().sources.get(&t.file).and_then(|lines| lines.get(t.line as usize).cloned()));
+    let number: String = (t.line + 1).to_string();
+    let gutter: String = " ".repeat(number.len());
+    println!("{} --> {}", gutter, location(t));
+    if let Some(text) = line {
+        // tabs are kept so the caret lines up with the quoted source
+        let indent: String = text.chars().take(t.column as usize).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        // the span covers what was written even after a pass rewrote the token (a folded expression,
+        // a renamed label) - tokens the assembler made up fall back to their text
+        let width: usize = if t.end > t.start {
+            let mut bytes: usize = 0;
+            text.chars().skip(t.column as usize).take_while(|c| { bytes += c.len_utf8(); bytes <= t.end - t.start }).count()
+        } else {
+            t.identifier.chars().count()
+        };
+        let width: usize = width.max(1);
+        println!("{} |", gutter);
+        println!("{} | {}", number, text);
+        println!("{} | {}{}", gutter, indent, "^".repeat(width));
+    }
+    for (name, file, line, column) in &t.expansion {
+        println!("{} = in macro {} called at {}", gutter, name, position(file, *line, *column));
+    }
+}
+
+fn emit(kind: &str, code: &str, err: &str, t: Option<&Token>) {
+    let show: bool = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let show: bool = !(s.quiet || kind == "error" && s.statement_failed);
+        if kind == "error" {
+            s.raised += 1;
+            s.statement_failed = true;
+        }
+        if show && kind == "error" {
+            s.errors += 1;
+        } else if show && kind == "warning" {
+            s.warnings += 1;
+        }
+        s.last_shown = show;
+        show
+    });
+    if !show {
+        return;
+    }
+    if code.is_empty() {
+        println!("{}: {}", kind, err);
+    } else {
+        println!("{}[{}]: {}", kind, code, err);
+    }
+    if let Some(t) = t {
+        excerpt(t);
+    }
+}
+
+pub fn error(code: &str, err: &str, t: &Token) {
+    emit("error", code, err, Some(t));
+}
+
+// for problems that belong to the whole program rather than one token
+pub fn error_without_location(code: &str, err: &str) {
+    emit("error", code, err, None);
+}
+
+pub fn warning(code: &str, err: &str, t: &Token) {
+    emit("warning", code, err, Some(t));
+}
+
+// extra context printed after an error, such as where a label was first defined
+pub fn note(err: &str, t: &Token) {
+    let shown: bool = STATE.with(|s| s.borrow().last_shown);
+    if shown {
+        println!("note: {}", err);
+        excerpt(t);
+    }
+}
@@ -4,1263 +4,2360 @@ use std::io::prelude::*;
 use std::env;
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+mod diagnostics;
+#[path = "../isa.rs"]
+mod isa;
+#[path = "../rbin.rs"]
+mod rbin;
+#[path = "../robj.rs"]
+mod robj;
+// the tests read listings back in to check the two agree
+#[cfg(test)]
+#[path = "../disassembler/main.rs"]
+#[allow(dead_code, clippy::duplicate_mod)]
+mod disassembler;
+
+const ROM_SIZE: usize = 0x10000;
+
+// what the lexer made of a token, so the passes don't have to look at the text again
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Mnemonic,
+    Register,
+    // the digits only - a # $ or % in front is a Punct of its own and decides the radix
+    Number { radix: u32 },
+    // labels, constants, macro names and local labels (.loop) used as operands
+    Symbol,
+    Punct,
+    Directive,
+    // the text keeps its opening quote and loses the closing one
+    String,
+}
 
-struct Token {
+#[derive(Clone)]
+pub struct Token {
+    kind: Kind,
     line: u16,
+    column: u16,
+    // byte offsets of the token in its source file, both zero for tokens the assembler makes up
+    start: usize,
+    end: usize,
     identifier: String,
+    // source file the token was read from, empty for tokens the assembler makes up
+    file: String,
+    // macro calls this token was expanded from, innermost first - (macro name, file, line and column
+    // of the call)
+    expansion: Vec<(String, String, u16, u16)>,
+}
+
+const REGISTERS: [&str; 7] = ["A", "B", "C", "D", "CD", "SP", "FP"];
+
+// tokens made up by the assembler don't know where they are, so they are typed on their own
+fn classify(s: &str) -> Kind {
+    let c: char = s.chars().next().unwrap_or(' ');
+    if c.is_ascii_digit() {
+        Kind::Number { radix: 10 }
+    } else if c == '.' {
+        Kind::Directive
+    } else if c == '"' {
+        Kind::String
+    } else if REGISTERS.contains(&s) {
+        Kind::Register
+    } else if isa::is_mnemonic(s) {
+        Kind::Mnemonic
+    } else if s.chars().any(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Kind::Symbol
+    } else {
+        Kind::Punct
+    }
 }
 
 fn create_token(x: u16, s: String) -> Token {
-    let t = Token {
+    
+    Token {
+        kind: classify(&s),
         line: x,
+        column: 0,
+        start: 0,
+        end: 0,
         identifier: s,
-    };
-    t
-}
-
-fn get_char(s: &String, i: usize) -> char {
-    let chars: Vec<char> = s.chars().skip(i).take(1).collect();
-    let c: char = chars[0];
-    c
+        file: String::new(),
+        expansion: Vec::new(),
+    }
 }
 
-fn peek_char(s: &String, i: usize) -> char {
-    let chars: Vec<char> = s.chars().skip(i).take(1).collect();
-    let c: char = chars[0];
-    c
+// the value of a number token in the radix it was written in, if it fits in T
+fn number<T: TryFrom<i64>>(t: &Token) -> Option<T> {
+    match t.kind {
+        Kind::Number { radix } => i64::from_str_radix(&t.identifier, radix).ok().and_then(|y| T::try_from(y).ok()),
+        _ => None,
+    }
 }
 
-fn tokenise(src: &String) -> Vec<Token> {
+// One pass over the source. A word is typed from the tokens before it: at the start of a statement
+// it is a mnemonic or directive, after # or $ it is hex and after % it is binary.
+fn tokenise(src: &str, file: &str) -> Vec<Token> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let at = |i: usize| -> char { if i < chars.len() { chars[i].1 } else { '\0' } };
+    let offset = |i: usize| -> usize { if i < chars.len() { chars[i].0 } else { src.len() } };
     let mut tokens: Vec<Token> = Vec::new();
     let mut line_number: u16 = 0;
-    let mut token = String::new();
-    let data_len = src.len();
-    let mut i = 0;
-    loop {
-        if i == data_len { break; }
-        let mut c = get_char(&src, i);
-        
-        while c.is_whitespace() {
-            i = i + 1;
-            c = get_char(&src, i);
-            if c == '\n' {
-                line_number = line_number + 1;
-            }
+    let mut line_start: usize = 0;
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let c: char = at(i);
+        if c == '\n' {
+            line_number += 1;
+            line_start = i + 1;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
         }
 
-        if c == '/' {
-            while c != '\n' {
-                i = i + 1;
-                c = get_char(&src, i);
+        let count: usize = tokens.len();
+        let previous: &str = if count > 0 { &tokens[count - 1].identifier[..] } else { "" };
+        let statement_start: bool = count == 0 || previous == ";" || (count > 1 && &tokens[count - 2].identifier[..] == ":");
+        let begin: usize = i;
+        let line: u16 = line_number;
+        let column: u16 = (begin - line_start) as u16;
+        let kind: Kind;
+
+        if c == '/' && (count < 2 || statement_start) {
+            // '/' divides inside an operand expression, anywhere else it starts a comment
+            while i < chars.len() && at(i) != '\n' {
+                i += 1;
             }
-            if c == '\n' {
-                line_number = line_number + 1;
+            continue;
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && at(i) != '"' {
+                if at(i) == '\n' {
+                    line_number += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
             }
-            i = i + 1;
-            c = get_char(&src, i);
-        }
-
-        if c.is_ascii_alphanumeric() {
-            if peek_char(&src, i + 1).is_ascii_alphanumeric() {
-                if c.is_numeric() {
-                    while c.is_numeric() {
-                        token.push(c);
-                        i = i + 1;
-                        c = get_char(&src, i);
-                    }
-                    let t: Token = create_token(line_number, token.to_string());
-                    tokens.push(t);
-                    token = String::new();
-                    continue;
-                }
+            kind = Kind::String;
+        } else if c == '.' {
+            while at(i) == '.' || at(i) == '_' || at(i).is_ascii_alphanumeric() {
+                i += 1;
+            }
+            kind = if statement_start { Kind::Directive } else { Kind::Symbol };
+        } else if c == '_' || c.is_ascii_alphanumeric() {
+            // identifiers may contain underscores (print_twice), hex digits may follow a leading decimal digit (#2a)
+            while at(i) == '_' || at(i).is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let word: &str = &src[offset(begin)..offset(i)];
+            let hex: bool = previous == "#" || previous == "$";
+            kind = if c.is_ascii_digit() {
+                Kind::Number { radix: if hex { 16 } else if previous == "%" { 2 } else { 10 } }
+            } else if hex && word.chars().all(|x| x.is_ascii_hexdigit()) {
+                Kind::Number { radix: 16 }
+            } else if REGISTERS.contains(&word) {
+                Kind::Register
+            } else if statement_start && isa::is_mnemonic(word) {
+                Kind::Mnemonic
             } else {
-                if peek_char(&src, i + 1) == ';' {
-                    token.push(c);
-                    i = i + 1;
-                    let t: Token = create_token(line_number, token.to_string());
-                    tokens.push(t);
-                    token = String::new();
-                    continue;
-                }
+                Kind::Symbol
+            };
+        } else if "<>=!&|".contains(c) {
+            // two character operators - << >> <= >= == != && ||
+            i += 1;
+            if at(i) == '=' || (at(i) == c && c != '!') {
+                i += 1;
+            }
+            kind = Kind::Punct;
+        } else if ",$:#%;[]+-*/^~()".contains(c) {
+            i += 1;
+            kind = Kind::Punct;
+        } else {
+            let mut t: Token = create_token(line, c.to_string());
+            t.column = column;
+            t.file = file.to_string();
+            report_error("E0003", &format!("Unexpected character {}", c), &t);
+            i += 1;
+            continue;
+        }
 
-                while c.is_ascii_alphabetic() {
-                    token.push(c);
-                    i = i + 1;
-                    c = get_char(&src, i);
-                }
-                let t: Token = create_token(line_number, token.to_string());
-                tokens.push(t);
-                token = String::new();
-                continue;
+        let mut t: Token = Token {
+            kind,
+            line,
+            column,
+            start: offset(begin),
+            end: offset(i),
+            identifier: src[offset(begin)..offset(i)].to_string(),
+            file: file.to_string(),
+            expansion: Vec::new(),
+        };
+        if kind == Kind::String {
+            if i == chars.len() {
+                report_error("E0015", "Unterminated string", &t);
+                break;
             }
+            // step over the closing quote
+            i += 1;
+            t.end = offset(i);
         }
+        tokens.push(t);
+    }
+    tokens
+}
 
-        token.push(c);
-        i = i + 1;
 
-        match &token[..] {
-            "," => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            "$" => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            ":" => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            "#" => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            "%" => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            ";" => { tokens.push(create_token(line_number, token.to_string())); token = String::new(); continue; },
-            _ => {},
+
+// tokens that came out of a macro carry the definition line, the call sites are listed after it
+fn report_error(code: &str, err: &str, t: &Token){
+    diagnostics::error(code, err, t);
+}
+
+// address operands are a single byte, or high then low byte in 16 bit mode
+fn write_address(rom: &mut [u8], rom_index: &mut usize, address: u16, wide: bool) {
+    if wide {
+        rom[*rom_index] = (address >> 8) as u8;
+        *rom_index += 1;
+    }
+    rom[*rom_index] = address as u8;
+    *rom_index += 1;
+}
+
+// [SP+n] / [FP-n] operands - i points at the opening bracket and is left on the closing one
+fn frame_operand(tokens: &[Token], i: &mut usize) -> Option<u8> {
+    let mut offset: i16 = 0;
+    *i += 2;
+    let mut t: &Token = &tokens[*i];
+    if &t.identifier[..] == "+" || &t.identifier[..] == "-" {
+        let negative: bool = &t.identifier[..] == "-";
+        *i += 1;
+        t = &tokens[*i];
+        let mut value: Option<i16> = None;
+        if &t.identifier[..] == "#" {
+            *i += 1;
+            t = &tokens[*i];
+            value = number::<i16>(t);
+        } else if let Some(y) = number::<i16>(t) {
+            value = Some(y);
         }
+        match value {
+            Some(y) => offset = if negative { -y } else { y },
+            None => {
+                report_error("E0005", "Invalid offset", t);
+                return None;
+            },
+        }
+        *i += 1;
+        t = &tokens[*i];
+    }
+    if &t.identifier[..] != "]" {
+        report_error("E0003", "Expected closing bracket", t);
+        return None;
     }
+    if !(-128..=127).contains(&offset) {
+        report_error("E0005", "Stack offset out of range", t);
+        return None;
+    }
+    Some(offset as u8)
+}
 
-    tokens
+fn is_frame_operand(tokens: &[Token], i: usize) -> bool {
+    &tokens[i].identifier[..] == "[" && (&tokens[i + 1].identifier[..] == "SP" || &tokens[i + 1].identifier[..] == "FP")
 }
 
-fn report_error(err: &str, line: u16){
-    println!("{} at line {}", err, line + 1);
+// the operands of the instruction at start as the ISA table writes them - a register, [SP+off],
+// [FP+off], [CD], $addr, #imm for # % and plain numbers, or label
+fn operand_forms(tokens: &[Token], start: usize) -> Vec<String> {
+    let end: usize = end_of_statement(tokens, start);
+    let mut forms: Vec<String> = Vec::new();
+    let mut operand: usize = start + 1;
+    while operand < end {
+        let mut stop: usize = operand;
+        while stop < end && &tokens[stop].identifier[..] != "," {
+            stop += 1;
+        }
+        let t: &Token = &tokens[operand];
+        let form: String = if stop == operand {
+            String::new()
+        } else if is_frame_operand(tokens, operand) {
+            format!("[{}+off]", tokens[operand + 1].identifier)
+        } else if &t.identifier[..] == "[" {
+            format!("[{}]", tokens[operand + 1].identifier)
+        } else if t.kind == Kind::Register {
+            t.identifier.clone()
+        } else if &t.identifier[..] == "$" {
+            "$addr".to_string()
+        } else if &t.identifier[..] == "#" || &t.identifier[..] == "%" || number::<i64>(t).is_some() {
+            "#imm".to_string()
+        } else if t.kind == Kind::Symbol {
+            "label".to_string()
+        } else {
+            t.identifier.clone()
+        };
+        forms.push(form);
+        operand = stop + 1;
+    }
+    forms
 }
 
-fn define_labels(tokens: &Vec<Token>) -> Option<HashMap<String, u8>> {
-    let mut labels = HashMap::new();
-    let mut rom_index: usize = 0;
-    let mut i = 0;
-    let token_length = tokens.len();
-    let mut had_error: bool = false;
+// for a statement whose operands match no table entry - a missing operand or comma is reported as
+// such rather than as an instruction the cpu doesn't have. Returns false when it reported one.
+fn check_operands(tokens: &[Token], start: usize) -> bool {
+    let end: usize = end_of_statement(tokens, start);
+    let mut i: usize = start + 1;
+    while i < end {
+        if &tokens[i].identifier[..] == "," {
+            report_error("E0004", "Expected an operand", &tokens[i]);
+            return false;
+        }
+        // an operand is a register, number or label, a prefix and a number or a bracketed operand
+        i = i + match &tokens[i].identifier[..] {
+            "[" => tokens[i..end].iter().position(|t| &t.identifier[..] == "]").map_or(end - i, |p| p + 1),
+            "$" | "#" | "%" => 2,
+            _ => 1,
+        };
+        if i >= end {
+            break;
+        }
+        if &tokens[i].identifier[..] != "," {
+            report_error("E0002", "Expected comma", &tokens[i]);
+            return false;
+        }
+        i += 1;
+        if i == end {
+            report_error("E0004", "Expected an operand", &tokens[i]);
+            return false;
+        }
+    }
+    true
+}
 
-    loop {
-        if i == token_length { break; }
-        if had_error { return None }
-        let mut t: &Token = &tokens[i];
+// branch targets may be a label, $ or # number, 16 bit immediates a label or a number
+fn fits(pattern: &str, written: &str) -> bool {
+    match pattern {
+        "addr" | "word" | "rel" => written == "$addr" || written == "#imm" || written == "label",
+        "#word" => written == "#imm" || written == "label",
+        _ => pattern == written,
+    }
+}
 
-        match &t.identifier[..] {
-            "MOV" => { 
-                i = i + 2;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line);
-                    had_error = true;
-                }
-                i = i + 1; 
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%" {
-                     i = i + 1;
-                }
-                rom_index = rom_index + 2;
-                i = i + 1; 
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "STR" => { 
-                i = i + 2;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line);
-                    had_error = true;
-                }
-                i = i + 1; 
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                     i = i + 1;
-                     rom_index = rom_index + 2;
-                } 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "PUSH" => {
-                i = i + 1;
-                t = &tokens[i];
-                if !(&t.identifier[..] == "A" || &t.identifier[..] == "A" || &t.identifier[..] == "A" || &t.identifier[..] == "A") {
-                    rom_index = rom_index + 1;
-                }
-                rom_index = rom_index + 1;
-                i = i + 1;
-            },
-            "POP" => {
-                i = i + 2;
-                rom_index = rom_index + 1;
-            },
-            "SWP" => {
-                i = i + 2;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line);
-                    had_error = true;
-                }
-                i = i + 2; 
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-                rom_index = rom_index + 1;
-            },
-            "JMP" => {
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 1;
-                }
-                rom_index = rom_index + 2;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "JEZ" => {
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 1;
-                }
-                rom_index = rom_index + 2;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "JNZ" => {
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 1;
-                }
-                rom_index = rom_index + 2;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "CALL" => {
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 1;
-                }
-                rom_index = rom_index + 2;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "RET" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "OUT" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "ADD" => {
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") || t.identifier[..].starts_with("B") || t.identifier[..].starts_with("C") || t.identifier[..].starts_with("D") {
-                    rom_index = rom_index + 1;
-                    i = i + 1;
-                } else if  &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 2;
-                    rom_index = rom_index + 2;
-                } else {
-                    i = i + 1;
-                    rom_index = rom_index + 2;
-                }
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
+// the table entry for the instruction at start, None when the cpu has no microcode for that form
+fn instruction_at(tokens: &[Token], start: usize) -> Option<&'static isa::Instruction> {
+    let mut mnemonic: &str = &tokens[start].identifier[..];
+    let mut forms: Vec<String> = operand_forms(tokens, start);
+    // swapping a register with itself does nothing
+    if mnemonic == "SWP" && tokens[start + 1].kind == Kind::Register && forms.len() == 2 && forms[0] == forms[1] {
+        mnemonic = "NOP";
+        forms.clear();
+    }
+    isa::INSTRUCTIONS.iter().find(|x| {
+        let operands: Vec<&str> = x.operand_list();
+        x.mnemonic == mnemonic && operands.len() == forms.len() && operands.iter().zip(forms.iter()).all(|(p, w)| fits(p, w))
+    })
+}
+
+// $ or # hex, % binary, otherwise decimal - i is left on the last token of the number
+fn read_number(tokens: &[Token], i: &mut usize) -> Option<usize> {
+    if tokens[*i].kind == Kind::Punct && ["$", "#", "%"].contains(&&tokens[*i].identifier[..]) {
+        *i += 1;
+    }
+    number::<usize>(&tokens[*i])
+}
+
+// operands wider than a byte - addresses in 16 bit mode, .dw/.org/.res/.align/.entry, far jumps and
+// MOV CD
+fn is_word_operand(mnemonic: &str, first: &str, prefix: &str, wide: bool) -> bool {
+    match mnemonic {
+        ".dw" | ".org" | ".res" | ".align" | ".entry" | "FJMP" | "FCALL" => true,
+        "MOV" if first == "CD" => true,
+        _ if is_rom_operand(mnemonic) => wide,
+        _ => prefix == "$" && wide,
+    }
+}
+
+// branch targets and LDR addresses point into ROM - without 16 bit addressing they are a byte in
+// the current bank
+fn is_rom_operand(mnemonic: &str) -> bool {
+    isa::INSTRUCTIONS.iter().any(|x| x.mnemonic == mnemonic && x.operand_list().iter().any(|p| *p == "addr" || *p == "rel"))
+}
+
+struct Expression<'a> {
+    tokens: &'a [Token],
+    at: usize,
+    labels: &'a HashMap<String, u16>,
+    constants: &'a HashMap<String, i64>,
+    here: usize,
+    final_pass: bool,
+    // code, message and the index of the offending token
+    error: Option<(&'static str, String, usize)>,
+}
+
+impl<'a> Expression<'a> {
+    fn peek(&self) -> &str {
+        if self.at < self.tokens.len() { &self.tokens[self.at].identifier[..] } else { "" }
+    }
+
+    // at is the index of the offending token, past the end means the end of the statement
+    fn fail(&mut self, code: &'static str, err: String, at: usize) -> i64 {
+        if self.error.is_none() {
+            self.error = Some((code, err, at.min(self.tokens.len() - 1)));
+        }
+        0
+    }
+
+    // lowest to highest precedence, as in C: || && | ^ & == != < > <= >= << >> + - * /
+    fn binary(&mut self, level: usize) -> i64 {
+        const LEVELS: [&[&str]; 10] = [&["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<", ">", "<=", ">="], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value: i64 = self.binary(level + 1);
+        while LEVELS[level].contains(&self.peek()) {
+            let op: String = self.peek().to_string();
+            let operator: usize = self.at;
+            self.at += 1;
+            let rhs: i64 = self.binary(level + 1);
+            value = match &op[..] {
+                "||" => (value != 0 || rhs != 0) as i64,
+                "&&" => (value != 0 && rhs != 0) as i64,
+                "==" => (value == rhs) as i64,
+                "!=" => (value != rhs) as i64,
+                "<" => (value < rhs) as i64,
+                ">" => (value > rhs) as i64,
+                "<=" => (value <= rhs) as i64,
+                ">=" => (value >= rhs) as i64,
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value + rhs,
+                "-" => value - rhs,
+                "*" => value * rhs,
+                _ => if rhs == 0 { self.fail("E0005", "Division by zero".to_string(), operator) } else { value / rhs },
+            };
+        }
+        value
+    }
+
+    fn unary(&mut self) -> i64 {
+        match self.peek() {
+            "-" => { self.at += 1; -self.unary() },
+            "+" => { self.at += 1; self.unary() },
+            "~" => { self.at += 1; !self.unary() },
+            "!" => { self.at += 1; (self.unary() == 0) as i64 },
+            _ => self.primary(),
+        }
+    }
+
+    // the value of the token under the cursor, if it is a number
+    fn literal(&self) -> Option<i64> {
+        self.tokens.get(self.at).and_then(number::<i64>)
+    }
+
+    fn primary(&mut self) -> i64 {
+        let word: String = self.peek().to_string();
+        let kind: Option<Kind> = self.tokens.get(self.at).map(|t| t.kind);
+        let value: Option<i64> = self.literal();
+        let index: usize = self.at;
+        self.at += 1;
+        match &word[..] {
+            "(" => {
+                let value: i64 = self.binary(0);
+                if self.peek() != ")" {
+                    return self.fail("E0003", "Expected closing parenthesis".to_string(), self.at);
+                }
+                self.at += 1;
+                value
             },
-            "SUB" => {
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") || t.identifier[..].starts_with("B") || t.identifier[..].starts_with("C") || t.identifier[..].starts_with("D") {
-                    rom_index = rom_index + 1;
-                    i = i + 1;
-                } else if  &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 2;
-                    rom_index = rom_index + 2;
-                } else {
-                    i = i + 1;
-                    rom_index = rom_index + 2;
-                }
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+            "#" | "%" => {
+                let digits: String = self.peek().to_string();
+                let value: Option<i64> = self.literal();
+                self.at += 1;
+                match value {
+                    Some(y) => y,
+                    None => self.fail("E0005", format!("Invalid number {}{}", word, digits), index + 1),
                 }
             },
-            "AND" => {
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") || t.identifier[..].starts_with("B") || t.identifier[..].starts_with("C") || t.identifier[..].starts_with("D") {
-                    rom_index = rom_index + 1;
-                    i = i + 1;
-                } else if  &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 2;
-                    rom_index = rom_index + 2;
-                } else {
-                    i = i + 1;
-                    rom_index = rom_index + 2;
-                }
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+            "$" => {
+                // $ followed by hex digits is an address, on its own it is the current address
+                match self.literal() {
+                    Some(y) => { self.at += 1; y },
+                    None => self.here as i64,
                 }
             },
-            "OR" => {
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") || t.identifier[..].starts_with("B") || t.identifier[..].starts_with("C") || t.identifier[..].starts_with("D") {
-                    rom_index = rom_index + 1;
-                    i = i + 1;
-                } else if  &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 2;
-                    rom_index = rom_index + 2;
-                } else {
-                    i = i + 1;
-                    rom_index = rom_index + 2;
-                }
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+            "LOW" | "HIGH" => {
+                if self.peek() != "(" {
+                    return self.fail("E0003", format!("Expected ( after {}", word), self.at);
                 }
+                let value: i64 = self.primary();
+                if word == "LOW" { value & 0xff } else { (value >> 8) & 0xff }
             },
-            "XOR" => {
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") || t.identifier[..].starts_with("B") || t.identifier[..].starts_with("C") || t.identifier[..].starts_with("D") {
-                    rom_index = rom_index + 1;
-                    i = i + 1;
-                } else if  &t.identifier[..] == "$" ||  &t.identifier[..] == "#" ||  &t.identifier[..] == "%"{
-                    i = i + 2;
-                    rom_index = rom_index + 2;
+            _ => {
+                if let Some(y) = value {
+                    y
+                } else if let Some(y) = self.constants.get(&word) {
+                    *y
+                } else if let Some(y) = self.labels.get(&word) {
+                    *y as i64
+                } else if kind != Some(Kind::Symbol) {
+                    self.at = index;
+                    self.fail("E0003", format!("Unexpected {} in expression", if word.is_empty() { "end of statement" } else { &word[..] }), index)
+                } else if self.final_pass {
+                    self.fail("E0006", format!("Undefined symbol {}", word), index)
                 } else {
-                    rom_index = rom_index + 2;
-                    i = i + 1;
-                }
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "NOT" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    // forward reference - only its size matters while labels are being defined
+                    0
                 }
             },
-            "DEC" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
+        }
+    }
+}
+
+fn evaluate(tokens: &[Token], labels: &HashMap<String, u16>, constants: &HashMap<String, i64>, here: usize, final_pass: bool) -> Result<i64, (&'static str, String, usize)> {
+    let mut expression: Expression = Expression { tokens, at: 0, labels, constants, here, final_pass, error: None };
+    let value: i64 = expression.binary(0);
+    if expression.error.is_none() && expression.at < tokens.len() {
+        let word: String = tokens[expression.at].identifier.clone();
+        let at: usize = expression.at;
+        expression.fail("E0003", format!("Unexpected {} in expression", word), at);
+    }
+    match expression.error {
+        Some(e) => Err(e),
+        None => Ok(value),
+    }
+}
+
+// .equ/.define constants and operand expressions are folded, one statement at a time, into the
+// #hex / $hex tokens the instruction parsers already understand. Both passes fold the same way so
+// the byte counts in define_labels match what assemble emits. Returns false on errors.
+fn fold_statement(tokens: &mut Vec<Token>, start: usize, labels: &HashMap<String, u16>, constants: &mut HashMap<String, i64>, here: usize, wide: bool, final_pass: bool) -> bool {
+    let mut end: usize = start;
+    while end < tokens.len() && &tokens[end].identifier[..] != ";" {
+        end += 1;
+    }
+    let mnemonic: String = tokens[start].identifier.clone();
+    if mnemonic == ".equ" || mnemonic == ".define" {
+        if end < start + 3 {
+            report_error("E0003", "Expected a name and a value", &tokens[start]);
+            return false;
+        }
+        let name: String = tokens[start + 1].identifier.clone();
+        let mut from: usize = start + 2;
+        if &tokens[from].identifier[..] == "," {
+            from += 1;
+        }
+        if from == end {
+            report_error("E0003", "Expected a name and a value", &tokens[start]);
+            return false;
+        }
+        match evaluate(&tokens[from..end], labels, constants, here, final_pass) {
+            Ok(y) => {
+                if final_pass && constants.contains_key(&name) {
+                    report_error("E0007", &format!("{} is already defined", name), &tokens[start]);
+                    return false;
+                }
+                constants.insert(name, y);
+                true
             },
-            "INC" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
+            Err((code, e, at)) => {
+                report_error(code, &e, &tokens[from + at]);
+                false
             },
-            "HALT" => {
-                rom_index = rom_index + 1;
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+        }
+    } else {
+        let mut ok: bool = true;
+        let mut operand: usize = start + 1;
+        let mut first: String = String::new();
+        while operand < end {
+            let mut stop: usize = operand;
+            let mut depth: i32 = 0;
+            while stop < end && !(depth == 0 && &tokens[stop].identifier[..] == ",") {
+                match &tokens[stop].identifier[..] {
+                    "(" | "[" => depth += 1,
+                    ")" | "]" => depth -= 1,
+                    _ => {},
+                }
+                stop += 1;
+            }
+            let count: usize = stop - operand;
+            let head: String = tokens[operand].identifier.clone();
+            if first.is_empty() {
+                first = head.clone();
+            }
+
+            // a lone label is a value like label+0, except for branches which resolve their own targets.
+            // Before the final pass a label can be further down and not defined yet.
+            let kind: Kind = tokens[operand].kind;
+            let branch: bool = is_rom_operand(&mnemonic) || mnemonic == "FJMP" || mnemonic == "FCALL";
+            let known: bool = labels.contains_key(&head) || (!final_pass && kind == Kind::Symbol && !constants.contains_key(&head));
+            let label: bool = count == 1 && known && !branch && (mnemonic == ".db" || !mnemonic.starts_with("."));
+            let literal: bool = (count == 1 && !constants.contains_key(&head) && head != "$" && !label) || (count == 2 && (head == "#" || head == "%" || (head == "$" && number::<usize>(&tokens[operand + 1]).is_some())));
+            let skip: bool = kind == Kind::String || kind == Kind::Register || head == "[" || (count == 1 && kind == Kind::Symbol && !constants.contains_key(&head) && !label);
+            if !skip && count > 0 {
+                // $ in front of an expression marks a memory address, anything else is a value
+                let address: bool = head == "$" && count > 1 && !["+", "-", "*", "/", "&", "|", "^", "<<", ">>"].contains(&&tokens[operand + 1].identifier[..]);
+                let from: usize = if address && !literal { operand + 1 } else { operand };
+                let word: bool = is_word_operand(&mnemonic, &first, if address { "$" } else { "" }, wide);
+                // the whole operand, so errors underline all of it
+                let mut span: Token = tokens[operand].clone();
+                let last: &Token = &tokens[stop - 1];
+                if last.file == span.file && last.line == span.line && last.end > span.start {
+                    span.end = last.end;
+                }
+                match evaluate(&tokens[from..stop], labels, constants, here, final_pass) {
+                    Ok(y) => {
+                        let (low, high): (i64, i64) = if word { (-0x8000, 0xffff) } else { (-0x80, 0xff) };
+                        // label+n in a higher bank is still a byte once the bank is dropped
+                        let banked: bool = !wide && is_rom_operand(&mnemonic) && y >= 0 && (y >> 8) as usize == here >> 8;
+                        if final_pass && (y < low || y > high) && !banked {
+                            report_error("E0005", &format!("Value {} doesn't fit in a {}", y, if word { "word" } else { "byte" }), &span);
+                            ok = false;
+                        }
+                        if !literal {
+                            let value: i64 = if word { y & 0xffff } else { y & 0xff };
+                            let mut folded: Vec<Token> = vec![span.clone(), span];
+                            folded[0].identifier = if address { "$".to_string() } else { "#".to_string() };
+                            folded[0].kind = Kind::Punct;
+                            folded[1].identifier = format!("{:x}", value);
+                            folded[1].kind = Kind::Number { radix: 16 };
+                            tokens.splice(operand..stop, folded);
+                            end = end + 2 - count;
+                            stop = operand + 2;
+                        }
+                    },
+                    Err((code, e, at)) => {
+                        report_error(code, &e, &tokens[from + at]);
+                        ok = false;
+                    },
                 }
-            },
-            ":" => {
-                i = i + 1;
-                t = &tokens[i];
-                labels.insert(t.identifier[..].to_string(), rom_index as u8);
-            },
-            _ => {},
+            }
+            operand = stop + 1;
+        }
+        ok
+    }
+}
+
+// a contiguous run of output started by .org, .bank or a section switch
+struct Region {
+    data: bool,
+    start: usize,
+    end: usize,
+    // the directive that started the region, for overlap errors
+    origin: Token,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+const MAX_MACRO_DEPTH: usize = 16;
+
+// .macro NAME a, b; ... .endm; - definitions are collected first so a macro can be used before it is
+// defined, then every call is replaced by the body with the arguments substituted
+fn expand_macros(tokens: Vec<Token>) -> Option<Vec<Token>> {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut program: Vec<Token> = Vec::new();
+    let mut had_error: bool = false;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let t: &Token = &tokens[i];
+        if &t.identifier[..] == ".endm" {
+            report_error("E0010", "Unexpected .endm", t);
+            had_error = true;
+            i += 1;
+            continue;
+        }
+        if &t.identifier[..] != ".macro" {
+            program.push(t.clone());
+            i += 1;
+            continue;
+        }
+
+        // header - name and comma separated parameter names up to the semicolon
+        i += 1;
+        if i >= tokens.len() || tokens[i].kind != Kind::Symbol {
+            report_error("E0003", "Expected macro name", t);
+            return None;
+        }
+        let name: String = tokens[i].identifier.clone();
+        let mut params: Vec<String> = Vec::new();
+        i += 1;
+        while i < tokens.len() && &tokens[i].identifier[..] != ";" {
+            if &tokens[i].identifier[..] != "," {
+                params.push(tokens[i].identifier.clone());
+            }
+            i += 1;
+        }
+        i += 1;
+
+        let mut body: Vec<Token> = Vec::new();
+        while i < tokens.len() && &tokens[i].identifier[..] != ".endm" {
+            if &tokens[i].identifier[..] == ".macro" {
+                report_error("E0010", "Macro definitions can't be nested", &tokens[i]);
+                had_error = true;
+            }
+            body.push(tokens[i].clone());
+            i += 1;
+        }
+        if i == tokens.len() {
+            report_error("E0010", &format!("Macro {} is missing .endm", name), t);
+            return None;
+        }
+        i += 1;
+        if i < tokens.len() && &tokens[i].identifier[..] == ";" {
+            i += 1;
+        }
+
+        if macros.contains_key(&name) {
+            report_error("E0007", &format!("Macro {} is already defined", name), t);
+            had_error = true;
+        }
+        macros.insert(name, Macro { params, body });
+    }
+
+    let mut expanded: Vec<Token> = Vec::new();
+    let mut count: usize = 0;
+    if !expand(&program, &macros, &mut count, 0, &mut expanded) || had_error {
+        return None;
+    }
+    Some(expanded)
+}
+
+fn expand(tokens: &[Token], macros: &HashMap<String, Macro>, count: &mut usize, depth: usize, out: &mut Vec<Token>) -> bool {
+    let mut ok: bool = true;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let t: &Token = &tokens[i];
+        if !macros.contains_key(&t.identifier) || !is_statement_start(tokens, i) {
+            out.push(t.clone());
+            i += 1;
+            continue;
+        }
+        let m: &Macro = &macros[&t.identifier];
+
+        // arguments are split on commas outside brackets, so expressions and [SP+n] can be passed
+        let mut args: Vec<Vec<Token>> = Vec::new();
+        let mut arg: Vec<Token> = Vec::new();
+        let mut nesting: i32 = 0;
+        i += 1;
+        while i < tokens.len() && &tokens[i].identifier[..] != ";" {
+            match &tokens[i].identifier[..] {
+                "(" | "[" => nesting += 1,
+                ")" | "]" => nesting -= 1,
+                _ => {},
+            }
+            if nesting == 0 && &tokens[i].identifier[..] == "," {
+                args.push(arg);
+                arg = Vec::new();
+            } else {
+                arg.push(tokens[i].clone());
+            }
+            i += 1;
+        }
+        if !arg.is_empty() || !args.is_empty() {
+            args.push(arg);
+        }
+        i += 1;
+
+        if args.len() != m.params.len() {
+            report_error("E0010", &format!("Macro {} expects {} arguments but was given {}", t.identifier, m.params.len(), args.len()), t);
+            ok = false;
+            continue;
+        }
+        if depth == MAX_MACRO_DEPTH {
+            report_error("E0010", &format!("Macro {} nests too deeply, is it calling itself?", t.identifier), t);
+            return false;
+        }
+
+        // labels defined in the body are renamed so every expansion gets its own
+        *count += 1;
+        let mut locals: HashMap<String, String> = HashMap::new();
+        for n in 1..m.body.len() {
+            if &m.body[n - 1].identifier[..] == ":" {
+                locals.insert(m.body[n].identifier.clone(), format!("{}.{}", m.body[n].identifier, count));
+            }
+        }
+
+        let mut body: Vec<Token> = Vec::new();
+        for b in &m.body {
+            if let Some(n) = m.params.iter().position(|p| *p == b.identifier) {
+                body.extend(args[n].iter().cloned());
+                continue;
+            }
+            let mut token: Token = b.clone();
+            if let Some(local) = locals.get(&b.identifier) {
+                token.identifier = local.clone();
+            }
+            token.expansion.push((t.identifier.clone(), t.file.clone(), t.line, t.column));
+            token.expansion.extend(t.expansion.iter().cloned());
+            body.push(token);
+        }
+        if !expand(&body, macros, count, depth + 1, out) {
+            ok = false;
         }
-        i = i + 1;
     }
-    
-    Some(labels)
+    ok
 }
 
-fn assemble(tokens: &Vec<Token>, labels: &HashMap<String, u8>) -> Option<[u8; 256]> {
-    let mut i = 0;
-    let mut rom_index: usize = 0;
-    let token_length = tokens.len();
-    let mut rom: [u8; 256] = [0; 256];
+// .if expr; .ifdef NAME; .ifndef NAME; .else; .endif; - decided from the .equ constants seen so far
+// and the -D definitions, before any addresses are worked out. Macro bodies are left alone so a
+// condition inside one can test the macro's arguments once it has been expanded.
+fn resolve_conditionals(tokens: Vec<Token>) -> Option<Vec<Token>> {
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let no_labels: HashMap<String, u16> = HashMap::new();
+    // one entry per open .if - (the .if token, whether its current branch is kept, whether any branch was, seen .else)
+    let mut open: Vec<(&Token, bool, bool, bool)> = Vec::new();
+    let mut program: Vec<Token> = Vec::new();
     let mut had_error: bool = false;
+    let mut i = 0;
 
-    loop {
-        if i == token_length { break; }
-        let mut t: &Token = &tokens[i];
-        let mut opcode: u8 = 0;
-        
-        match &t.identifier[..] {
-            "MOV" => {
-                opcode = opcode | (0x0 << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "A" {
-                    opcode = opcode | (0x00);
-                } else if &t.identifier[..] == "B" {
-                    opcode = opcode | (0x01);
-                } else if &t.identifier[..] == "C" {
-                    opcode = opcode | (0x02);
-                } else if &t.identifier[..] == "D" {
-                    opcode = opcode | (0x03);
-                } else {
-                    report_error("Invalid operand", t.line);
-                    had_error = true;
-                }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line);
-                    had_error = true;
-                }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" {
-                    opcode = opcode | (0x00 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier,16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
+    while i < tokens.len() {
+        let t: &Token = &tokens[i];
+        let active: bool = open.iter().all(|c| c.1);
+        let mut end: usize = i;
+        while end < tokens.len() && &tokens[end].identifier[..] != ";" {
+            end += 1;
+        }
+        if !is_statement_start(&tokens, i) {
+            if active {
+                program.push(t.clone());
+            }
+            i += 1;
+            continue;
+        }
 
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+        match &t.identifier[..] {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let mut keep: bool = false;
+                if active {
+                    if end == i + 1 {
+                        report_error("E0003", &format!("Expected a condition after {}", t.identifier), t);
+                        had_error = true;
+                    } else if &t.identifier[..] == ".if" {
+                        match evaluate(&tokens[i + 1..end], &no_labels, &constants, 0, true) {
+                            Ok(y) => keep = y != 0,
+                            Err((code, e, at)) => {
+                                report_error(code, &e, &tokens[i + 1 + at]);
+                                had_error = true;
+                            },
+                        }
+                    } else if end == i + 2 {
+                        keep = constants.contains_key(&tokens[i + 1].identifier) == (&t.identifier[..] == ".ifdef");
+                    } else {
+                        report_error("E0003", &format!("Expected a single name after {}", t.identifier), t);
+                        had_error = true;
                     }
                 }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                open.push((t, keep, keep, false));
+            },
+            ".else" | ".endif" if end != i + 1 => {
+                report_error("E0001", "Expected semicolon", t);
+                had_error = true;
+            },
+            ".else" => {
+                match open.pop() {
+                    Some((start, _, taken, false)) => open.push((start, !taken, true, true)),
+                    Some((start, _, _, true)) => {
+                        report_error("E0011", ".if already has an .else", t);
+                        open.push((start, false, true, true));
+                        had_error = true;
+                    },
+                    None => {
+                        report_error("E0011", ".else without .if", t);
+                        had_error = true;
+                    },
+                }
+            },
+            ".endif" => {
+                if open.pop().is_none() {
+                    report_error("E0011", ".endif without .if", t);
                     had_error = true;
-                    continue;
                 }
             },
-            "STR" =>{
-                opcode = opcode | (0x1 << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "A" {
-                    opcode = opcode | (0x00);
-                } else if &t.identifier[..] == "B" {
-                    opcode = opcode | (0x01);
-                } else if &t.identifier[..] == "C" {
-                    opcode = opcode | (0x02);
-                } else if &t.identifier[..] == "D" {
-                    opcode = opcode | (0x03);
+            ".macro" => {
+                while end < tokens.len() && &tokens[end].identifier[..] != ".endm" {
+                    end += 1;
                 }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line);
-                    had_error = true;
+                if active {
+                    program.extend(tokens[i..end].iter().cloned());
                 }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" {
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+                i = end;
+                continue;
+            },
+            _ => {
+                if active {
+                    if (&t.identifier[..] == ".equ" || &t.identifier[..] == ".define") && end > i + 2 {
+                        // constants that depend on labels can't be known yet and are left to the passes
+                        let from: usize = if &tokens[i + 2].identifier[..] == "," { i + 3 } else { i + 2 };
+                        if from == end {
+                            // reported by the passes
+                        } else if let Ok(y) = evaluate(&tokens[from..end], &no_labels, &constants, 0, true) {
+                            constants.insert(tokens[i + 1].identifier.clone(), y);
+                        }
                     }
-                } 
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    program.extend(tokens[i..(end + 1).min(tokens.len())].iter().cloned());
                 }
+                i = end + 1;
+                continue;
             },
-            "PUSH" =>{
-                opcode = opcode | (0x2 << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "A" {
-                    opcode = opcode | (0x00);
-                } else if &t.identifier[..] == "B" {
-                    opcode = opcode | (0x01);
-                } else if &t.identifier[..] == "C" {
-                    opcode = opcode | (0x02);
-                } else if &t.identifier[..] == "D" {
-                    opcode = opcode | (0x03);
-                } else {
-                    opcode = opcode | (0x04);
-                }
+        }
+        // the conditional directives themselves are dropped, with their semicolon
+        i = end + 1;
+    }
 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
+    for (start, _, _, _) in &open {
+        report_error("E0011", &format!("{} is missing .endif", start.identifier), start);
+        had_error = true;
+    }
+    if had_error {
+        return None;
+    }
+    Some(program)
+}
 
-                if opcode & 0x04 == 0x04 {
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } 
+// Local labels (.loop) belong to the last global label before them and become global.loop.
+// Anonymous labels (:- and :+) get a unique name, and an operand of just - or + signs refers to the
+// nearest one before or after it - every extra sign skips one more (JNZ --;).
+fn scope_labels(tokens: Vec<Token>) -> Option<Vec<Token>> {
+    let mut anonymous: Vec<(usize, String)> = Vec::new();
+    for i in 1..tokens.len() {
+        if &tokens[i - 1].identifier[..] == ":" && (&tokens[i].identifier[..] == "-" || &tokens[i].identifier[..] == "+") {
+            anonymous.push((i, tokens[i].identifier.clone()));
+        }
+    }
+    let name = |n: usize| -> String { format!("anonymous~{}", n) };
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "POP" =>{
-                opcode = opcode | (0x3 << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "A" {
-                    opcode = opcode | (0x00);
-                } else if &t.identifier[..] == "B" {
-                    opcode = opcode | (0x01);
-                } else if &t.identifier[..] == "C" {
-                    opcode = opcode | (0x02);
-                } else if &t.identifier[..] == "D" {
-                    opcode = opcode | (0x03);
-                }
+    let mut program: Vec<Token> = Vec::new();
+    let mut scope: Option<String> = None;
+    let mut had_error: bool = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let mut t: Token = tokens[i].clone();
+
+        if i > 0 && &tokens[i - 1].identifier[..] == ":" {
+            // a label definition
+            if let Some(n) = anonymous.iter().position(|a| a.0 == i) {
+                t.identifier = name(n);
+                t.kind = Kind::Symbol;
+            } else if t.identifier.starts_with(".") {
+                match &scope {
+                    Some(global) => t.identifier = format!("{}{}", global, t.identifier),
+                    None => {
+                        report_error("E0006", &format!("Local label {} has no global label before it", t.identifier), &t);
+                        had_error = true;
+                    },
+                }
+            } else if !t.identifier.contains(".") {
+                // labels made by macro expansion (again.3) don't start a scope
+                scope = Some(t.identifier.clone());
+            }
+            program.push(t);
+            i += 1;
+            continue;
+        }
 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
+        let operand: bool = i > 0 && (&tokens[i - 1].identifier[..] == "," || is_statement_start(&tokens, i - 1));
+        if operand && (&t.identifier[..] == "-" || &t.identifier[..] == "+") {
+            let mut end: usize = i;
+            while end < tokens.len() && tokens[end].identifier == t.identifier {
+                end += 1;
+            }
+            if end == tokens.len() || &tokens[end].identifier[..] == "," || &tokens[end].identifier[..] == ";" {
+                let skip: usize = end - i - 1;
+                let target: Option<usize> = if &t.identifier[..] == "-" {
+                    (0..anonymous.len()).rev().filter(|n| anonymous[*n].0 < i && anonymous[*n].1 == "-").nth(skip)
+                } else {
+                    (0..anonymous.len()).filter(|n| anonymous[*n].0 > i && anonymous[*n].1 == "+").nth(skip)
+                };
+                match target {
+                    Some(n) => {
+                        t.identifier = name(n);
+                        t.kind = Kind::Symbol;
+                        t.end = tokens[end - 1].end;
+                    },
+                    None => {
+                        report_error("E0006", &format!("No anonymous label for {}", tokens[i..end].iter().map(|x| &x.identifier[..]).collect::<String>()), &t);
+                        had_error = true;
+                    },
+                }
+                program.push(t);
+                i = end;
+                continue;
+            }
+        }
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+        if !is_statement_start(&tokens, i) && t.kind == Kind::Symbol && t.identifier.starts_with(".") {
+            // a reference to a local label
+            match &scope {
+                Some(global) => t.identifier = format!("{}{}", global, t.identifier),
+                None => {
+                    report_error("E0006", &format!("Local label {} has no global label before it", t.identifier), &t);
                     had_error = true;
-                }
-            },
-            "SWP" => {
-                opcode = opcode | (0x4 << 4); 
-                i = i + 1;
-                t = &tokens[i];
+                },
+            }
+        }
+        program.push(t);
+        i += 1;
+    }
 
-                if &t.identifier[..] == "A" {
-                    opcode = opcode | (0x00 << 2);
-                } else if &t.identifier[..] == "B" {
-                    opcode = opcode | (0x01 << 2);
-                } else if &t.identifier[..] == "C" {
-                    opcode = opcode | (0x02 << 2);
-                } else if &t.identifier[..] == "D" {
-                    opcode = opcode | (0x03 << 2);
-                }
+    if had_error {
+        return None;
+    }
+    Some(program)
+}
 
-                let reg: String = t.identifier.clone();
+// the passes look a few tokens ahead without bounds checks, so a truncated last statement reads
+// semicolons instead of running off the end
+const SENTINELS: usize = 8;
+
+fn with_sentinels(source: &[Token]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = source.to_owned();
+    let mut end: Token = match source.last() { Some(t) => t.clone(), None => create_token(0, String::new()) };
+    end.column += end.identifier.chars().count() as u16;
+    end.identifier = ";".to_string();
+    end.kind = Kind::Punct;
+    end.start = end.end;
+    for _ in 0..SENTINELS {
+        tokens.push(end.clone());
+    }
+    tokens
+}
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != "," {
-                    report_error("Expected comma", t.line)
-                }
+// Folding an operand changes the number of tokens, so the passes don't hold the whole program in
+// one vector - tokens already read are in `tokens` and the rest are in `pending`, last token first.
+// This moves the statement at i and a few tokens after it across, keeping every fold near the end.
+fn read_ahead(tokens: &mut Vec<Token>, pending: &mut Vec<Token>, i: usize) {
+    let mut end: usize = i;
+    loop {
+        if end >= tokens.len() {
+            match pending.pop() {
+                Some(t) => tokens.push(t),
+                None => return,
+            }
+            continue;
+        }
+        if &tokens[end].identifier[..] == ";" {
+            break;
+        }
+        end += 1;
+    }
+    while tokens.len() <= end + SENTINELS {
+        match pending.pop() {
+            Some(t) => tokens.push(t),
+            None => return,
+        }
+    }
+}
 
-                i = i + 1;
-                t = &tokens[i];
+fn end_of_statement(tokens: &[Token], start: usize) -> usize {
+    let mut end: usize = start;
+    while end < tokens.len() - 1 && &tokens[end].identifier[..] != ";" {
+        end += 1;
+    }
+    end
+}
 
-                if t.identifier == reg {
-                    rom[rom_index] = 0xF0; // NOP
-                } else {
-                    if &t.identifier[..] == "A" {
-                        opcode = opcode | (0x00);
-                    } else if &t.identifier[..] == "B" {
-                        opcode = opcode | (0x01);
-                    } else if &t.identifier[..] == "C" {
-                        opcode = opcode | (0x02);
-                    } else if &t.identifier[..] == "D" {
-                        opcode = opcode | (0x03);
-                    }
-                    rom[rom_index] = opcode;
-                }
-                rom_index = rom_index + 1;    
+// operands are folded once per statement, at its first token
+fn is_statement_start(tokens: &[Token], i: usize) -> bool {
+    if &tokens[i].identifier[..] == ":" || &tokens[i].identifier[..] == ";" {
+        return false;
+    }
+    i == 0 || &tokens[i - 1].identifier[..] == ";" || (i > 1 && &tokens[i - 2].identifier[..] == ":")
+}
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+fn is_wide(tokens: &[Token]) -> bool {
+    tokens.iter().any(|t| &t.identifier[..] == ".wide")
+}
+
+// problems are only counted here - assemble reads the program the same way and reports them
+fn define_labels(source: &[Token], wide: bool) -> HashMap<String, u16> {
+    let mut labels = HashMap::new();
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut pending: Vec<Token> = with_sentinels(source);
+    pending.reverse();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut rom_index: usize = 0;
+    let mut ram_index: usize = 0; // the other section's counter while .data is selected
+    let mut data: bool = false;
+    let mut i = 0;
+    let mut token_length = pending.len() - SENTINELS;
+    let mut statement_end: usize = 0;
+    let mut errors: usize = 0;
+    diagnostics::set_quiet(true);
+
+    loop {
+        if i >= token_length { break; }
+        read_ahead(&mut tokens, &mut pending, i);
+        if is_statement_start(&tokens, i) {
+            diagnostics::begin_statement();
+            errors = diagnostics::errors_raised();
+            fold_statement(&mut tokens, i, &labels, &mut constants, rom_index, wide, false);
+            token_length = tokens.len() + pending.len() - SENTINELS;
+            statement_end = end_of_statement(&tokens, i);
+        }
+        let tokens: &Vec<Token> = &tokens;
+        let mut t: &Token = &tokens[i];
+
+        match &t.identifier[..] {
+            _ if t.kind == Kind::Mnemonic && is_statement_start(tokens, i) => {
+                // the operands decide the form, the form decides the size
+                if let Some(instruction) = instruction_at(tokens, i) {
+                    rom_index += instruction.size(wide);
                 }
+                i = statement_end;
             },
-            "JMP" =>{
-                opcode = opcode | (0x5 << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-                
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] == "$" || &t.identifier[..] == "#" {
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+            ".db" => {
+                i += 1;
+                t = &tokens[i];
+                while &t.identifier[..] != ";" {
+                    if t.kind == Kind::String {
+                        rom_index = rom_index + t.identifier.len() - 1;
+                    } else if !(&t.identifier[..] == "," || &t.identifier[..] == "#" || &t.identifier[..] == "%") {
+                        rom_index += 1;
                     }
-                } else if labels.contains_key(&t.identifier[..].to_string()) {
-                    rom[rom_index] = labels[&t.identifier[..].to_string()] as u8;
-                    rom_index = rom_index + 1;
-                } else {
-                    // handle error
-                }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    i += 1;
+                    t = &tokens[i];
                 }
             },
-            "JEZ" =>{
-                opcode = opcode | (0x6 << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-                
-                i = i + 1;
+            ".dw" => {
+                i += 1;
                 t = &tokens[i];
-                if &t.identifier[..] == "$" || &t.identifier[..] == "#" {
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+                while &t.identifier[..] != ";" {
+                    if !(&t.identifier[..] == "," || &t.identifier[..] == "#" || &t.identifier[..] == "%" || &t.identifier[..] == "$") {
+                        rom_index += 2;
                     }
-                } else if labels.contains_key(&t.identifier[..].to_string()) {
-                    rom[rom_index] = labels[&t.identifier[..].to_string()] as u8;
-                    rom_index = rom_index + 1;
-                } else {
-                    // handle error
+                    i += 1;
+                    t = &tokens[i];
                 }
-
-                i = i + 1;
+            },
+            ".org" | ".align" | ".res" => {
+                let directive: &str = &t.identifier[..];
+                i += 1;
+                match read_number(tokens, &mut i) {
+                    Some(y) if directive == ".org" => rom_index = y,
+                    Some(y) if directive == ".align" && y > 0 => rom_index = rom_index.div_ceil(y) * y,
+                    Some(y) if directive == ".res" => rom_index += y,
+                    _ => {
+                        report_error("E0004", "Invalid operand", &tokens[i]);
+                    },
+                }
+                i += 1;
                 t = &tokens[i];
                 if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    report_error("E0001", "Expected semicolon", t);
                 }
             },
-            "JNZ" =>{
-                opcode = opcode | (0x7 << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-                
-                i = i + 1;
+            ".string" | ".asciz" => {
+                i += 1;
                 t = &tokens[i];
-                if &t.identifier[..] == "$" || &t.identifier[..] == "#" {
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if labels.contains_key(&t.identifier[..].to_string()) {
-                    rom[rom_index] = labels[&t.identifier[..].to_string()] as u8;
-                    rom_index = rom_index + 1;
+                if t.kind == Kind::String {
+                    rom_index += t.identifier.len();
                 } else {
-                    // handle error
+                    report_error("E0003", "Expected string", t);
                 }
-
-                i = i + 1;
+                i += 1;
                 t = &tokens[i];
                 if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    report_error("E0001", "Expected semicolon", t);
                 }
             },
-            "CALL" =>{
-                opcode = opcode | (0x8 << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-                
-                i = i + 1;
+            ".bank" => {
+                i += 1;
                 t = &tokens[i];
-                if &t.identifier[..] == "$" || &t.identifier[..] == "#" {
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if labels.contains_key(&t.identifier[..].to_string()) {
-                    rom[rom_index] = labels[&t.identifier[..].to_string()] as u8;
-                    rom_index = rom_index + 1;
+                if let Some(y) = number::<usize>(t) {
+                    rom_index = y * 256;
                 } else {
-                    // handle error
+                    report_error("E0005", "Invalid bank", t);
                 }
-
-                i = i + 1;
+                i += 1;
                 t = &tokens[i];
                 if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+                    report_error("E0001", "Expected semicolon", t);
                 }
             },
-            "RET" =>{
-                opcode = opcode | (0x9 << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
+            ".wide" => {
+                i += 1;
             },
-            "OUT" => {
-                opcode = opcode | (0xa << 4); 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+            ".equ" | ".define" | ".global" | ".extern" => {
+                // constants are evaluated by fold_statement, symbols are handled by assemble_object
+                while i < token_length - 1 && &tokens[i].identifier[..] != ";" {
+                    i += 1;
                 }
             },
-            "ADD" => {
-                opcode = opcode | (0xb << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") {
-                    opcode = opcode | (0x00);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("B") {
-                    opcode = opcode | (0x01);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("C") {
-                    opcode = opcode | (0x02);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("D") {
-                    opcode = opcode | (0x03);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if  &t.identifier[..] == "$"{
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if  &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if  &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    } 
-                } else {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
+            ".data" | ".code" => {
+                // labels in the data section are RAM addresses
+                if data != (&t.identifier[..] == ".data") {
+                    std::mem::swap(&mut rom_index, &mut ram_index);
+                    data = !data;
                 }
+                i += 1;
             },
-            "SUB" => {
-                opcode = opcode | (0xc << 4); 
-                i = i + 1;
+            ":" => {
+                i += 1;
                 t = &tokens[i];
-                if t.identifier[..].starts_with("A") {
-                    opcode = opcode | (0x00);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("B") {
-                    opcode = opcode | (0x01);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("C") {
-                    opcode = opcode | (0x02);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("D") {
-                    opcode = opcode | (0x03);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if  &t.identifier[..] == "$"{
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if  &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if  &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
+                labels.insert(t.identifier[..].to_string(), rom_index as u16);
+            },
+            _ => {},
+        }
+        // after an error carry on from the next statement, as assemble does
+        if diagnostics::errors_raised() > errors && i < statement_end {
+            i = statement_end;
+        }
+        i += 1;
+    }
 
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
+    diagnostics::set_quiet(false);
+    labels
+}
 
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                }
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
-                    had_error = true;
-                }
-            },
-            "AND" => {
-                opcode = opcode | (0xd << 4); 
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") {
-                    opcode = opcode | (0x00);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("B") {
-                    opcode = opcode | (0x01);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("C") {
-                    opcode = opcode | (0x02);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("D") {
-                    opcode = opcode | (0x03);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if  &t.identifier[..] == "$" {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
+// returns the ROM image, the initial RAM image built from .data sections and the entry point
+fn assemble(source: &[Token], labels: &HashMap<String, u16>, wide: bool) -> Option<(Vec<u8>, Vec<u8>, u16)> {
+    let mut i = 0;
+    let mut rom_index: usize = 0;
+    let mut pending: Vec<Token> = with_sentinels(source);
+    pending.reverse();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut token_length = pending.len() - SENTINELS;
+    let mut definitions: HashMap<String, Token> = HashMap::new();
+    let mut statement_end: usize = 0;
+    let mut errors: usize = 0;
+    // set after an instruction that never falls through, cleared by a label
+    let mut unreachable: Option<String> = None;
+    let mut rom: Vec<u8> = vec![0; ROM_SIZE];
+    let mut had_error: bool = false;
+    let mut bank_end: usize = 256;
+    let mut image_end: usize = 256;
+    // while .data is selected rom/rom_index hold the RAM image and these hold the code
+    let mut ram: Vec<u8> = vec![0; ROM_SIZE];
+    let mut ram_index: usize = 0;
+    let mut data: bool = false;
+    // execution starts at the first byte of ROM unless .entry says otherwise
+    let mut entry: Option<(u16, Token)> = None;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut region_start: usize = 0;
+    let mut region_origin: Token = match source.first() { Some(t) => t.clone(), None => create_token(0, String::new()) };
+    // the table entry of the current statement, the opcode and operand forms come from it
+    let mut instruction: Option<&'static isa::Instruction> = None;
+    let mut statement_start: usize = 0;
 
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
+    loop {
+        if i >= token_length { break; }
+        read_ahead(&mut tokens, &mut pending, i);
+        if is_statement_start(&tokens, i) {
+            diagnostics::begin_statement();
+            errors = diagnostics::errors_raised();
+            if !fold_statement(&mut tokens, i, labels, &mut constants, rom_index, wide, true) {
+                had_error = true;
+            }
+            token_length = tokens.len() + pending.len() - SENTINELS;
+            // a program too big for ROM is reported after the loop, until then the image grows
+            if rom.len() < rom_index + ROM_SIZE {
+                rom.resize(rom_index + ROM_SIZE, 0);
+            }
+            statement_end = end_of_statement(&tokens, i);
+            statement_start = i;
+            instruction = if tokens[i].kind == Kind::Mnemonic { instruction_at(&tokens, i) } else { None };
+
+            let word: &String = &tokens[i].identifier;
+            if tokens[i].kind == Kind::Directive {
+                unreachable = None;
+            } else if let Some(after) = &unreachable {
+                diagnostics::warning("W0002", &format!("Unreachable instruction after {}", after), &tokens[i]);
+                unreachable = None;
+            }
+            if ["HALT", "JMP", "FJMP", "JR", "RET", "FRET"].contains(&&word[..]) {
+                unreachable = Some(word.clone());
+            }
+        }
+        let tokens: &Vec<Token> = &tokens;
+        let mut t: &Token = &tokens[i];
 
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
+        if data && t.kind != Kind::Directive && &t.identifier[..] != ":" {
+            report_error("E0009", "Only data directives are allowed in the data section", t);
+            had_error = true;
+        }
+        
+        match &t.identifier[..] {
+            ".db" => {
+                i += 1;
+                t = &tokens[i];
+                while &t.identifier[..] != ";" {
+                    if &t.identifier[..] == "," {
+                        // separator
+                    } else if &t.identifier[..] == "#" || &t.identifier[..] == "%" {
+                        i += 1;
+                        t = &tokens[i];
+                        if let Some(y) = number::<u8>(t) {
+                            rom[rom_index] = y;
+                        }
+                        rom_index += 1;
+                    } else if t.kind == Kind::String {
+                        for b in t.identifier[1..].bytes() {
+                            rom[rom_index] = b;
+                            rom_index += 1;
+                        }
+                    } else if labels.contains_key(&t.identifier[..].to_string()) {
+                        rom[rom_index] = labels[&t.identifier[..].to_string()] as u8;
+                        rom_index += 1;
+                    } else if let Some(y) = number::<u8>(t) {
                         rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+                        rom_index += 1;
+                    } else {
+                        report_error("E0005", "Invalid byte", t);
+                        had_error = true;
+                        rom_index += 1;
                     }
-                } else {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+                    i += 1;
+                    t = &tokens[i];
+                }
+            },
+            ".dw" => {
+                i += 1;
+                t = &tokens[i];
+                while &t.identifier[..] != ";" {
+                    if &t.identifier[..] == "," {
+                        // separator
+                    } else if labels.contains_key(&t.identifier[..].to_string()) {
+                        write_address(&mut rom, &mut rom_index, labels[&t.identifier[..].to_string()], true);
+                    } else {
+                        match read_number(tokens, &mut i) {
+                            Some(y) if y <= 0xffff => write_address(&mut rom, &mut rom_index, y as u16, true),
+                            _ => {
+                                report_error("E0005", "Invalid word", &tokens[i]);
+                                had_error = true;
+                                rom_index += 2;
+                            },
+                        }
                     }
+                    i += 1;
+                    t = &tokens[i];
                 }
-
-                i = i + 1;
+            },
+            ".org" | ".bank" => {
+                let bank: bool = &t.identifier[..] == ".bank";
+                i += 1;
                 t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                if bank && wide {
+                    report_error("E0009", "Banks can't be used in 16 bit mode", t);
                     had_error = true;
                 }
-            },
-            "OR" => {
-                opcode = opcode | (0xd << 4);
-                opcode = opcode | 0x4; 
-                i = i + 1;
-                t = &tokens[i];
-                if t.identifier[..].starts_with("A") {
-                    opcode = opcode | (0x00);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("B") {
-                    opcode = opcode | (0x01);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("C") {
-                    opcode = opcode | (0x02);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("D") {
-                    opcode = opcode | (0x03);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if  &t.identifier[..] == "$" {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
+                if bank && data {
+                    report_error("E0009", "Banks can't be selected in the data section", t);
+                    had_error = true;
+                }
+                if !data && !wide && rom_index > bank_end {
+                    report_error("E0009", "Bank overflow", t);
+                    had_error = true;
+                }
+                let origin: &Token = t;
+                let address: Option<usize> = if bank {
+                    number::<usize>(t).map(|y| y * 256)
                 } else {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
+                    read_number(tokens, &mut i)
+                };
+                match address {
+                    Some(y) if y < ROM_SIZE => {
+                        regions.push(Region { data, start: region_start, end: rom_index, origin: region_origin.clone() });
+                        image_end = image_end.max(rom_index);
+                        rom_index = y;
+                        region_start = y;
+                        region_origin = origin.clone();
+                        if !data && !wide {
+                            bank_end = (y / 256 + 1) * 256;
+                            image_end = image_end.max(bank_end);
+                        }
+                    },
+                    _ => {
+                        report_error("E0005", "Invalid address", origin);
+                        had_error = true;
+                    },
                 }
-
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                i += 1;
+            },
+            ".align" | ".res" => {
+                let directive: &str = &t.identifier[..];
+                i += 1;
+                match read_number(tokens, &mut i) {
+                    Some(y) if directive == ".align" && y > 0 => rom_index = rom_index.div_ceil(y) * y,
+                    Some(y) if directive == ".res" => rom_index += y,
+                    _ => {
+                        report_error("E0004", "Invalid operand", &tokens[i]);
+                        had_error = true;
+                    },
+                }
+                if rom_index > ROM_SIZE {
+                    report_error("E0005", "Address out of range", &tokens[i]);
                     had_error = true;
+                    rom_index = ROM_SIZE;
                 }
+                i += 1;
             },
-            "XOR" => {
-                opcode = opcode | (0xe << 4); 
-                i = i + 1;
+            ".string" | ".asciz" => {
+                i += 1;
                 t = &tokens[i];
-                if t.identifier[..].starts_with("A") {
-                    opcode = opcode | (0x00);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("B") {
-                    opcode = opcode | (0x01);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("C") {
-                    opcode = opcode | (0x02);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if t.identifier[..].starts_with("D") {
-                    opcode = opcode | (0x03);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-                } else if  &t.identifier[..] == "$" {
-                    opcode = opcode | (0x01 << 2);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "#" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 16) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
-                } else if &t.identifier[..] == "%" {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    i = i + 1;
-                    t = &tokens[i];
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 2) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
+                if t.kind == Kind::String {
+                    for b in t.identifier[1..].bytes() {
+                        rom[rom_index] = b;
+                        rom_index += 1;
                     }
+                    rom[rom_index] = 0x00;
+                    rom_index += 1;
                 } else {
-                    opcode = opcode | (0x01 << 3);
-                    rom[rom_index] = opcode;
-                    rom_index = rom_index + 1;
-
-                    if let Ok(y) = u8::from_str_radix(&t.identifier, 10) {
-                        rom[rom_index] = y;
-                        rom_index = rom_index + 1;
-                    }
+                    report_error("E0003", "Expected string", t);
+                    had_error = true;
                 }
 
-                i = i + 1;
+                i += 1;
                 t = &tokens[i];
                 if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                    report_error("E0001", "Expected semicolon", t);
                     had_error = true;
                 }
             },
-            "NOT" => {
-                opcode = opcode | (0xe << 4);
-                opcode = opcode | 0x4; 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-
-                i = i + 1;
+            ".wide" => {
+                i += 1;
+            },
+            ".entry" => {
+                i += 1;
+                t = &tokens[i];
+                let mut address: Option<u16> = None;
+                if &t.identifier[..] == "$" || &t.identifier[..] == "#" {
+                    i += 1;
+                    t = &tokens[i];
+                    address = u16::from_str_radix(&t.identifier, 16).ok();
+                } else if labels.contains_key(&t.identifier[..].to_string()) {
+                    address = Some(labels[&t.identifier[..].to_string()]);
+                }
+                match address {
+                    Some(_) if entry.is_some() => {
+                        report_error("E0007", "The entry point is already set", t);
+                        had_error = true;
+                    },
+                    Some(_) if data => {
+                        report_error("E0009", "The entry point can't be set in the data section", t);
+                        had_error = true;
+                    },
+                    Some(y) => entry = Some((y, t.clone())),
+                    None => {
+                        report_error("E0005", "Invalid entry point", t);
+                        had_error = true;
+                    },
+                }
+                i += 1;
                 t = &tokens[i];
                 if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                    report_error("E0001", "Expected semicolon", t);
                     had_error = true;
                 }
             },
-            "INC" => {
-                opcode = 0xA2; 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
-
-                i = i + 1;
+            ".equ" | ".define" | ".global" | ".extern" => {
+                // constants are evaluated by fold_statement, symbols are handled by assemble_object
+                while i < token_length - 1 && &tokens[i].identifier[..] != ";" {
+                    i += 1;
+                }
+            },
+            ".data" | ".code" => {
+                if data != (&t.identifier[..] == ".data") {
+                    regions.push(Region { data, start: region_start, end: rom_index, origin: region_origin.clone() });
+                    std::mem::swap(&mut rom, &mut ram);
+                    std::mem::swap(&mut rom_index, &mut ram_index);
+                    data = !data;
+                    region_start = rom_index;
+                    region_origin = t.clone();
+                }
+                i += 1;
+            },
+            ":" => {
+                diagnostics::begin_statement();
+                i += 1;
                 t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                if let Some(first) = definitions.get(&t.identifier) {
+                    report_error("E0007", &format!("Label {} is already defined", t.identifier), t);
+                    diagnostics::note("first defined here", first);
                     had_error = true;
                 }
+                definitions.insert(t.identifier.clone(), t.clone());
+                unreachable = None;
             },
-            "DEC" => {
-                opcode = 0xA1; 
-                rom[rom_index] = opcode;
-                rom_index = rom_index + 1;
+            // every instruction is encoded from its table entry, the operands in the order the table
+            // writes them
+            _ if t.kind == Kind::Mnemonic && is_statement_start(tokens, i) => {
+                match instruction {
+                    // reported after the match, once the operands are known to be well formed
+                    None => {
+                        if !check_operands(tokens, statement_start) {
+                            had_error = true;
+                        }
+                        i = statement_end;
+                    },
+                    Some(x) => {
+                        rom[rom_index] = x.opcode;
+                        rom_index += 1;
+                        // SWP of a register with itself is the NOP it assembles to
+                        if x.mnemonic != &t.identifier[..] {
+                            i = statement_end - 1;
+                        }
+                        for (n, pattern) in x.operand_list().into_iter().enumerate() {
+                            i += 1;
+                            t = &tokens[i];
+                            if n > 0 {
+                                if &t.identifier[..] != "," {
+                                    report_error("E0002", "Expected comma", t);
+                                    had_error = true;
+                                }
+                                i += 1;
+                                t = &tokens[i];
+                            }
+                            match pattern {
+                                "#imm" => {
+                                    match read_number(tokens, &mut i) {
+                                        Some(y) => rom[rom_index] = y as u8,
+                                        None => {
+                                            report_error("E0004", "Invalid operand", t);
+                                            had_error = true;
+                                        },
+                                    }
+                                    rom_index += 1;
+                                },
+                                "$addr" => {
+                                    match read_number(tokens, &mut i) {
+                                        Some(y) => write_address(&mut rom, &mut rom_index, y as u16, wide),
+                                        None => {
+                                            report_error("E0004", "Invalid operand", t);
+                                            had_error = true;
+                                        },
+                                    }
+                                },
+                                "addr" | "rel" | "word" | "#word" => {
+                                    let label: Option<u16> = labels.get(&t.identifier).copied();
+                                    let value: Option<u16> = match label {
+                                        Some(y) => Some(y),
+                                        None => read_number(tokens, &mut i).map(|y| y as u16),
+                                    };
+                                    let address: u16 = match value {
+                                        Some(y) => y,
+                                        None => {
+                                            if t.kind == Kind::Symbol {
+                                                report_error("E0006", &format!("Unknown label {}", t.identifier), t);
+                                            } else {
+                                                report_error("E0004", "Invalid operand", t);
+                                            }
+                                            had_error = true;
+                                            0
+                                        },
+                                    };
+                                    // without 16 bit addressing a branch can't leave the bank, a far
+                                    // target keeps the bank in its high byte
+                                    if !wide && label.is_some() && (pattern == "addr" || pattern == "rel") && (address >> 8) as usize != rom_index >> 8 {
+                                        report_error("E0008", "Label is in another bank", t);
+                                        had_error = true;
+                                    }
+                                    if pattern == "rel" {
+                                        // the offset is taken from the address of the next instruction,
+                                        // a number is an address in the current bank
+                                        let target: usize = if !wide && label.is_none() { (rom_index & !0xff) | (address as usize & 0xff) } else { address as usize };
+                                        let offset: i32 = target as i32 - (rom_index + 1) as i32;
+                                        if value.is_some() && !(-128..=127).contains(&offset) {
+                                            report_error("E0008", "Relative jump out of range", t);
+                                            had_error = true;
+                                        }
+                                        rom[rom_index] = offset as u8;
+                                        rom_index += 1;
+                                    } else {
+                                        write_address(&mut rom, &mut rom_index, address, wide || pattern != "addr");
+                                    }
+                                },
+                                "[SP+off]" | "[FP+off]" => {
+                                    match frame_operand(tokens, &mut i) {
+                                        Some(offset) => rom[rom_index] = offset,
+                                        None => had_error = true,
+                                    }
+                                    rom_index += 1;
+                                },
+                                "[CD]" => {
+                                    i += 2;
+                                    t = &tokens[i];
+                                    if &t.identifier[..] != "]" {
+                                        report_error("E0003", "Expected closing bracket", t);
+                                        had_error = true;
+                                    }
+                                },
+                                // registers are part of the opcode
+                                _ => {},
+                            }
+                        }
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+                        i += 1;
+                        t = &tokens[i];
+                        if &t.identifier[..] != ";" {
+                            report_error("E0001", "Expected semicolon", t);
+                            had_error = true;
+                        }
+                    },
+                }
+            },
+            _ => {
+                if is_statement_start(tokens, i) && t.kind == Kind::Directive {
+                    diagnostics::warning("W0001", &format!("Unknown directive {} is ignored", t.identifier), t);
+                } else if is_statement_start(tokens, i) {
+                    report_error("E0014", &format!("Unknown instruction {}", t.identifier), t);
                     had_error = true;
                 }
             },
-            "HALT" => { 
-                rom[rom_index] = 0xff; 
-                rom_index = rom_index + 1;
+        }
+        // the operands parsed but the cpu has no microcode for that combination
+        if i == statement_end && tokens[statement_start].kind == Kind::Mnemonic && instruction.is_none() && diagnostics::errors_raised() == errors {
+            let forms: String = operand_forms(tokens, statement_start).join(", ");
+            let mut span: Token = tokens[statement_start].clone();
+            if statement_end > statement_start + 1 {
+                let last: &Token = &tokens[statement_end - 1];
+                span = tokens[statement_start + 1].clone();
+                if last.file == span.file && last.line == span.line && last.end > span.start {
+                    span.end = last.end;
+                }
+            }
+            if forms.is_empty() {
+                report_error("E0004", &format!("There is no {} instruction without operands", tokens[statement_start].identifier), &span);
+            } else {
+                report_error("E0004", &format!("There is no {} {} instruction", tokens[statement_start].identifier, forms), &span);
+            }
+            had_error = true;
+        }
+        // after an error carry on from the next statement, anything else it reports is fallout
+        if diagnostics::errors_raised() > errors && i < statement_end {
+            i = statement_end;
+        }
+        i += 1;
+    }
+    tokens.extend(pending.drain(..).rev());
+    // not repeated when the last statement already failed
+    if token_length > 0 && &tokens[token_length - 1].identifier[..] != ";" && !(token_length > 1 && &tokens[token_length - 2].identifier[..] == ":") {
+        report_error("E0001", "Expected semicolon at the end of the file", &tokens[token_length]);
+        had_error = true;
+    }
 
-                i = i + 1;
-                t = &tokens[i];
-                if &t.identifier[..] != ";" {
-                    report_error("Expected semicolon", t.line);
+    regions.push(Region { data, start: region_start, end: rom_index, origin: region_origin.clone() });
+    if data {
+        std::mem::swap(&mut rom, &mut ram);
+        std::mem::swap(&mut rom_index, &mut ram_index);
+    }
+
+    // .org regions may be given in any order but must not overlap
+    regions.retain(|r| r.end > r.start);
+    regions.sort_by(|x, y| x.data.cmp(&y.data).then(x.start.cmp(&y.start)));
+    for n in 1..regions.len() {
+        if regions[n].data == regions[n - 1].data && regions[n].start < regions[n - 1].end {
+            report_error("E0009", &format!("Region at {:#06x} overlaps the one at {:#06x}", regions[n].start, regions[n - 1].start), &regions[n].origin);
+            had_error = true;
+        }
+    }
+    let code_end: usize = regions.iter().filter(|r| !r.data).map(|r| r.end).max().unwrap_or(0);
+    let data_end: usize = regions.iter().filter(|r| r.data).map(|r| r.end).max().unwrap_or(0);
+    // the upper half of the first RAM page belongs to the stack
+    if !wide && data_end > 0x80 {
+        report_error("E0009", "Data section runs into the stack", &tokens[token_length - 1]);
+        had_error = true;
+    }
+
+    let rom_size: usize = if wide { code_end.max(256) } else { image_end };
+    if code_end > rom_size || code_end > ROM_SIZE || (!wide && rom_index > bank_end) {
+        report_error("E0009", "Program does not fit in ROM", &tokens[token_length - 1]);
+        had_error = true;
+    }
+    if let Some((address, origin)) = &entry {
+        if *address as usize >= rom_size {
+            report_error("E0009", "The entry point is outside the program", origin);
+            had_error = true;
+        }
+    }
+
+    if had_error {
+        None
+    } else {
+        rom.truncate(rom_size);
+        ram.truncate(data_end);
+        Some((rom, ram, entry.map_or(0, |e| e.0)))
+    }
+}
+
+// reads a source file and splices in every .include "file.rsm"; - names are looked up next to the
+// including file first, then in each -I directory. `stack` holds the files currently being read so a
+// cycle is reported instead of recursing forever
+fn load_source(path: &Path, include_dirs: &[PathBuf], stack: &mut Vec<PathBuf>) -> Option<Vec<Token>> {
+    let mut src: String = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+        diagnostics::error_without_location("E0012", &format!("Can't read {}: {}", path.display(), e));
+        return None;
+    }
+    stack.push(path.canonicalize().unwrap_or(path.to_path_buf()));
+
+    diagnostics::add_source(&path.display().to_string(), &src);
+    let errors: usize = diagnostics::errors_raised();
+    let tokens: Vec<Token> = tokenise(&src, &path.display().to_string());
+    let mut program: Vec<Token> = Vec::new();
+    let mut had_error: bool = diagnostics::errors_raised() > errors;
+    let mut i = 0;
+    while i < tokens.len() {
+        let t: &Token = &tokens[i];
+        if &t.identifier[..] != ".include" || !is_statement_start(&tokens, i) {
+            program.push(t.clone());
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        if i >= tokens.len() || tokens[i].kind != Kind::String {
+            report_error("E0003", "Expected a quoted file name", t);
+            had_error = true;
+            continue;
+        }
+        let name: String = tokens[i].identifier[1..].to_string();
+        i += 1;
+        if i >= tokens.len() || &tokens[i].identifier[..] != ";" {
+            report_error("E0001", "Expected semicolon", t);
+            had_error = true;
+            continue;
+        }
+        i += 1;
+
+        let mut candidates: Vec<PathBuf> = vec![path.parent().unwrap_or(Path::new("")).join(&name)];
+        for dir in include_dirs {
+            candidates.push(dir.join(&name));
+        }
+        match candidates.into_iter().find(|c| c.is_file()) {
+            Some(found) => {
+                if stack.contains(&found.canonicalize().unwrap_or(found.clone())) {
+                    report_error("E0012", &format!("{} includes itself", name), t);
                     had_error = true;
+                } else {
+                    match load_source(&found, include_dirs, stack) {
+                        Some(included) => program.extend(included),
+                        None => had_error = true,
+                    }
                 }
             },
-            ":" => {
-                i = i + 1;
+            None => {
+                report_error("E0012", &format!("Can't find include file {}", name), t);
+                had_error = true;
+            },
+        }
+    }
+
+    stack.pop();
+    if had_error {
+        return None;
+    }
+    Some(program)
+}
+
+// the ROM image, RAM image and labels of one assembly run of an object
+type Layout = (Vec<u8>, Vec<u8>, HashMap<String, u16>);
+
+// A relocatable object is assembled several times: as written, with the code section moved up a
+// byte, with the data section moved up a byte and with each import set to 1 in turn. A byte that
+// follows exactly one of those moves holds an 8 bit address and gets a relocation for it.
+fn assemble_object(tokens: &[Token]) -> Option<robj::Object> {
+    let mut exports: Vec<(String, &Token)> = Vec::new();
+    let mut imports: Vec<String> = Vec::new();
+    let mut had_error: bool = false;
+    for i in 0..tokens.len() {
+        let t: &Token = &tokens[i];
+        match &t.identifier[..] {
+            ".wide" | ".org" | ".bank" | ".align" | ".entry" => {
+                report_error("E0013", &format!("{} can't be used in a relocatable object", t.identifier), t);
+                had_error = true;
+            },
+            ".global" | ".extern" => {
+                let mut n = i + 1;
+                while n < tokens.len() && &tokens[n].identifier[..] != ";" {
+                    if &tokens[n].identifier[..] != "," {
+                        if &t.identifier[..] == ".global" {
+                            exports.push((tokens[n].identifier.clone(), t));
+                        } else {
+                            imports.push(tokens[n].identifier.clone());
+                        }
+                    }
+                    n += 1;
+                }
             },
             _ => {},
         }
-        i = i + 1;
+    }
+    if had_error {
+        return None;
+    }
+
+    // (code shift, data shift, import set to 1) - the first layout is the module as written
+    let mut layouts: Vec<(usize, usize, Option<usize>)> = vec![(0, 0, None), (1, 0, None), (0, 1, None)];
+    for n in 0..imports.len() {
+        layouts.push((0, 0, Some(n)));
+    }
+    let mut images: Vec<Layout> = Vec::new();
+    for (code, data, import) in &layouts {
+        let mut words: Vec<String> = Vec::new();
+        for (n, name) in imports.iter().enumerate() {
+            let value: &str = if *import == Some(n) { "1" } else { "0" };
+            words.extend([".equ", &name[..], ",", value, ";"].iter().map(|w| w.to_string()));
+        }
+        if *code == 1 {
+            words.extend([".org", "1", ";"].iter().map(|w| w.to_string()));
+        }
+        if *data == 1 {
+            words.extend([".data", ";", ".res", "1", ";", ".code", ";"].iter().map(|w| w.to_string()));
+        }
+        let mut source: Vec<Token> = words.into_iter().map(|w| create_token(0, w)).collect();
+        source.extend(tokens.iter().cloned());
+        // the section lengths are read back from these labels
+        source.extend([".code", ";", ":", "~code", ".data", ";", ":", "~data"].iter().map(|w| create_token(0, w.to_string())));
+
+        let labels: HashMap<String, u16> = define_labels(&source, false);
+        // the extra layouts would repeat every warning of the first, so only their failure is reported
+        let extra: bool = !images.is_empty();
+        diagnostics::set_quiet(extra);
+        let assembled = assemble(&source, &labels, false);
+        diagnostics::set_quiet(false);
+        let (rom, ram, _) = match assembled {
+            Some(y) => y,
+            None if extra => {
+                diagnostics::error_without_location("E0013", "The module only assembles where it was written, it can't be relocated");
+                return None;
+            },
+            None => return None,
+        };
+        images.push((rom, ram, labels));
+    }
+
+    let base: &HashMap<String, u16> = &images[0].2;
+    for name in &imports {
+        if base.contains_key(name) {
+            diagnostics::error_without_location("E0013", &format!("{} is imported but also defined in this module", name));
+            had_error = true;
+        }
+    }
+    let code_len: usize = base["~code"] as usize;
+    let data_len: usize = base["~data"] as usize;
+    let mut object: robj::Object = robj::Object {
+        isa: rbin::ISA_VERSION,
+        code: images[0].0[..code_len].to_vec(),
+        data: images[0].1[..data_len].to_vec(),
+        exports: Vec::new(),
+        imports: imports.clone(),
+        relocations: Vec::new(),
+    };
+
+    for (section, length) in [(robj::SECTION_CODE, code_len), (robj::SECTION_DATA, data_len)].iter() {
+        for k in 0..*length {
+            let byte = |run: usize| -> u8 {
+                let shift: usize = if *section == robj::SECTION_CODE { layouts[run].0 } else { layouts[run].1 };
+                if *section == robj::SECTION_CODE { images[run].0[k + shift] } else { images[run].1[k + shift] }
+            };
+            let mut targets: Vec<robj::Relocation> = Vec::new();
+            let mut relocatable: bool = true;
+            for (run, layout) in layouts.iter().enumerate().skip(1) {
+                match byte(run).wrapping_sub(byte(0)) {
+                    0 => {},
+                    1 => targets.push(robj::Relocation {
+                        section: *section,
+                        offset: k as u16,
+                        target: if layout.0 == 1 { robj::TARGET_CODE } else if layout.1 == 1 { robj::TARGET_DATA } else { robj::TARGET_IMPORT },
+                        import: layout.2.unwrap_or(0) as u16,
+                    }),
+                    _ => relocatable = false,
+                }
+            }
+            if !relocatable || targets.len() > 1 {
+                diagnostics::error_without_location("E0013", &format!("The address at {} offset {:#04x} can't be relocated, only an address plus a constant can", if *section == robj::SECTION_CODE { "code" } else { "data" }, k));
+                had_error = true;
+            }
+            object.relocations.extend(targets);
+        }
+    }
+
+    for (name, t) in &exports {
+        if !base.contains_key(name) {
+            report_error("E0013", &format!("Exported symbol {} isn't a label", name), t);
+            had_error = true;
+            continue;
+        }
+        // a label moves with the section it is in
+        let section: u8 = if images[1].2[name] != base[name] { robj::SECTION_CODE } else { robj::SECTION_DATA };
+        object.exports.push((name.clone(), section, base[name]));
     }
 
     if had_error {
-        None
-    } else {
-        Some(rom)
+        return None;
     }
+    Some(object)
+}
+
+fn report_failure() {
+    println!("Failed to assemble source code ({} errors, {} warnings).", diagnostics::error_count(), diagnostics::warning_count());
 }
 
 fn validate_filetype(src: &String) -> bool {
-    let v: Vec<&str> = src.split(".").collect();
-    v[1] == "rsm"
+    Path::new(src).extension().is_some_and(|e| e == "rsm")
+}
+
+// --info prints the header and symbol table of a container
+fn print_container(filename: &String) -> std::io::Result<()> {
+    let mut data: Vec<u8> = Vec::new();
+    File::open(filename)?.read_to_end(&mut data)?;
+    if !rbin::is_container(&data) {
+        println!("{}: raw image, {} bytes", filename, data.len());
+        return Ok(());
+    }
+    match rbin::decode(&data) {
+        Ok(image) => {
+            println!("{}: container version {}, ISA version {}", filename, image.version, image.isa);
+            println!("addressing: {} bit", if image.wide { 16 } else { 8 });
+            println!("entry point: {:04x}", image.entry);
+            println!("initial SP: {:02x}", image.sp);
+            println!("ROM: {} bytes", image.rom.len());
+            println!("RAM: {} bytes", image.ram.len());
+            for (name, address) in &image.symbols {
+                println!("{:04x} {}", address, name);
+            }
+        },
+        Err(e) => { println!("{}: {}", filename, e); },
+    }
+    Ok(())
 }
 
 fn main() ->std::io::Result<()> {
     
-    let mut src_str = String::new();
-
     let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    if !validate_filetype(&filename) {
+    let mut include_dirs: Vec<PathBuf> = Vec::new();
+    let mut defines: Vec<String> = Vec::new();
+    let mut source: Option<&String> = None;
+    let mut n = 1;
+    while n < args.len() {
+        if &args[n][..] == "-I" && n + 1 < args.len() {
+            include_dirs.push(PathBuf::from(&args[n + 1]));
+            n += 1;
+        } else if args[n].starts_with("-I") {
+            include_dirs.push(PathBuf::from(&args[n][2..]));
+        } else if &args[n][..] == "-D" && n + 1 < args.len() {
+            defines.push(args[n + 1].clone());
+            n += 1;
+        } else if args[n].starts_with("-D") {
+            defines.push(args[n][2..].to_string());
+        } else if !args[n].starts_with("--") && &args[n][..] != "DEBUG" && source.is_none() {
+            source = Some(&args[n]);
+        }
+        n += 1;
+    }
+    let filename: &String = match source {
+        Some(f) => f,
+        None => {
+            println!("Usage: assembler [--container | --object] [--info] [-I dir]... [-D NAME[=value]]... <source.rsm> [DEBUG]");
+            return Ok(());
+        },
+    };
+    if args.iter().any(|arg| arg == "--info") {
+        return print_container(filename);
+    }
+    if !validate_filetype(filename) {
         println!("Invalid file type. Only .rsm files can be assembled.");
         return Ok(());
     }
 
-    let mut f = File::open(filename)?;
-    f.read_to_string(&mut src_str)?;
-
-    let tokens: Vec<Token> = tokenise(&src_str);
+    // -D NAME=value is the same as starting the program with .equ NAME, value;
+    let mut command_line: String = String::new();
+    for define in &defines {
+        match define.split_once('=') {
+            Some((name, value)) => command_line.push_str(&format!(".equ {}, {};", name, value)),
+            None => command_line.push_str(&format!(".equ {}, 1;", define)),
+        }
+    }
+    let source: Option<Vec<Token>> = load_source(Path::new(filename), &include_dirs, &mut Vec::new()).map(|program| {
+        diagnostics::add_source("command line", &command_line);
+        let mut tokens: Vec<Token> = tokenise(&command_line, "command line");
+        tokens.extend(program);
+        tokens
+    });
+    let tokens: Vec<Token> = match source.and_then(resolve_conditionals).and_then(expand_macros).and_then(resolve_conditionals).and_then(scope_labels) {
+        Some(tokens) => tokens,
+        None => {
+            report_failure();
+            return Ok(());
+        },
+    };
     
-    let labels = define_labels(&tokens);
-    match labels {
-        None =>  { println!{"Failed to assemble source code."}; },
-        Some(labels) => {
-            let rom = assemble(&tokens, &labels);
-            match rom {
-                Some(rom) => {
-                    if args.len() == 3 {
-                        if args[2] == "DEBUG" {
-                            for i in 0..256 {
-                                println!("ROM [{}] -- {}", i, rom[i]);
-                            }
-                        }
-                    }
-        
-                    let output_filename = filename.split(".").next();
-                    match output_filename {
-                        Some(output_filename) => { 
-                            let mut name: String = output_filename.to_owned();
-                            name.push_str(".rbin");
-                            let mut output = File::create(name)?;
-                            output.write(&rom)?; 
-                
-                        },
-                        None => {},
-                    }
-                },
-                None => { println!{"Failed to assemble source code."}; },
+    if args.iter().any(|arg| arg == "--object") {
+        match assemble_object(&tokens) {
+            Some(object) => File::create(Path::new(filename).with_extension("robj"))?.write_all(&robj::encode(&object))?,
+            None => report_failure(),
+        }
+        return Ok(());
+    }
+    if let Some(t) = tokens.iter().find(|t| &t.identifier[..] == ".extern") {
+        report_error("E0013", "Imported symbols need --object and the linker", t);
+        report_failure();
+        return Ok(());
+    }
+
+    let wide: bool = is_wide(&tokens);
+    let labels = define_labels(&tokens, wide);
+    match assemble(&tokens, &labels, wide) {
+        Some((rom, ram, entry)) => {
+            if args.iter().any(|arg| arg == "DEBUG") {
+                for (i, byte) in rom.iter().enumerate() {
+                    println!("ROM [{}] -- {}", i, byte);
+                }
+            }
+
+            let name = Path::new(filename).with_extension("rbin");
+            let mut output = File::create(name)?;
+            if args.iter().any(|arg| arg == "--container") {
+                let mut image: rbin::Image = rbin::new_image(rom, wide);
+                image.ram = ram;
+                image.entry = entry;
+                let mut symbols: Vec<(String, u16)> = labels.into_iter().collect();
+                symbols.sort_by(|x, y| x.1.cmp(&y.1).then(x.0.cmp(&y.0)));
+                image.symbols = symbols;
+                output.write_all(&rbin::encode(&image))?;
+            } else {
+                output.write_all(&rom)?;
+                // raw images get the data section alongside, for cpu --ram
+                if !ram.is_empty() {
+                    File::create(Path::new(filename).with_extension("ram"))?.write_all(&ram)?;
+                }
             }
-        }  
+        },
+        None => report_failure(),
     }
     
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_source(src: &str) -> Option<Vec<u8>> {
+        let tokens: Vec<Token> = resolve_conditionals(tokenise(src, "test.rsm")).and_then(expand_macros).and_then(resolve_conditionals).and_then(scope_labels)?;
+        let wide: bool = is_wide(&tokens);
+        let labels: HashMap<String, u16> = define_labels(&tokens, wide);
+        assemble(&tokens, &labels, wide).map(|(rom, _, _)| rom)
+    }
+
+    #[test]
+    fn memory_operands() {
+        let rom = assemble_source("STR #ff, $10;\nINC $10;\nSTR #5, $11;\nDEC $11;\nHALT;").unwrap();
+        assert_eq!(&rom[..11], &[0x14, 0xff, 0x10, 0xa6, 0x10, 0x14, 0x05, 0x11, 0xa5, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn data_directives() {
+        let rom = assemble_source("LDR A, table, D;\nHALT;\n:table\n.db #1, %10, 3;\n.string \"hi\";").unwrap();
+        assert_eq!(&rom[..9], &[0x0c, 0x03, 0xff, 0x01, 0x02, 0x03, b'h', b'i', 0x00]);
+    }
+
+    #[test]
+    fn multiply_and_divide() {
+        let rom = assemble_source("MUL;\nDIV;\nMOD;\nHALT;").unwrap();
+        assert_eq!(&rom[..4], &[0xf1, 0xf2, 0xf3, 0xff]);
+    }
+
+    #[test]
+    fn block_instructions() {
+        let rom = assemble_source("LDIR;\nFILL;\nHALT;").unwrap();
+        assert_eq!(&rom[..3], &[0xf4, 0xf5, 0xff]);
+    }
+
+    #[test]
+    fn wide_addresses() {
+        let rom = assemble_source(".wide;\nJMP far;\nMOV CD, #0120;\nMOV A, [CD];\nSTR A, $0200;\n:far\nHALT;").unwrap();
+        assert_eq!(&rom[..11], &[0x50, 0x00, 0x0a, 0x38, 0x01, 0x20, 0x3c, 0x10, 0x02, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn banks_and_far_calls() {
+        let rom = assemble_source("FCALL far;\nHALT;\n.bank 1;\n:far\nFRET;").unwrap();
+        assert_eq!(rom.len(), 0x200);
+        assert_eq!(&rom[..4], &[0x81, 0x01, 0x00, 0xff]);
+        assert_eq!(rom[0x100], 0x91);
+        assert!(assemble_source(".wide;\n.bank 1;\nHALT;").is_none());
+    }
+
+    #[test]
+    fn relative_offsets() {
+        let rom = assemble_source("JR skip;\nMOV A, #1;\n:skip\nCALLR sub;\n:top\nJRNZ top;\nHALT;\n:sub\nRET;").unwrap();
+        assert_eq!(&rom[..9], &[0x52, 0x02, 0x04, 0x01, 0x82, 0x03, 0x72, 0xfe, 0xff]);
+    }
+
+    #[test]
+    fn conditional_mnemonics() {
+        let rom = assemble_source("JC end;\nJNC end;\nCALLZ end;\nCALLNZ end;\nRETZ;\nRETNZ;\n:end\nHALT;").unwrap();
+        assert_eq!(&rom[..11], &[0x63, 0x0a, 0x73, 0x0a, 0x83, 0x0a, 0x84, 0x0a, 0x93, 0x94, 0xff]);
+    }
+
+    #[test]
+    fn context_save_mnemonics() {
+        let rom = assemble_source("PUSHF;\nPUSHALL;\nPOPALL;\nPOPF;\nHALT;").unwrap();
+        assert_eq!(&rom[..5], &[0x25, 0x26, 0x36, 0x35, 0xff]);
+    }
+
+    #[test]
+    fn frame_operands() {
+        let rom = assemble_source("ENTER #2;\nMOV A, [FP-3];\nSTR A, [FP+1];\nMOV B, [SP-1];\nSTR C, [SP];\nLEAVE;").unwrap();
+        assert_eq!(&rom[..11], &[0x27, 0x02, 0x28, 0xfd, 0x2c, 0x01, 0x19, 0xff, 0x1e, 0x00, 0x37]);
+        assert!(assemble_source("MOV A, [SP+200];").is_none());
+    }
+
+    #[test]
+    fn system_call() {
+        let rom = assemble_source("SYS #2;\nSYS 0;\nHALT;").unwrap();
+        assert_eq!(&rom[..5], &[0xaa, 0x02, 0xaa, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn entry_point() {
+        let entry = |src: &str| -> Option<u16> {
+            let tokens: Vec<Token> = tokenise(src, "test.rsm");
+            let labels: HashMap<String, u16> = define_labels(&tokens, false);
+            assemble(&tokens, &labels, false).map(|(_, _, entry)| entry)
+        };
+        assert_eq!(entry("HALT;\nHALT;"), Some(0));
+        assert_eq!(entry(".entry main;\nHALT;\n:main\nHALT;"), Some(1));
+        assert_eq!(entry(".entry $2;\nHALT;"), Some(2));
+        assert_eq!(entry(".entry main;\n.entry main;\n:main\nHALT;"), None);
+        assert_eq!(entry(".entry missing;\nHALT;"), None);
+        assert_eq!(entry(".entry $1ff;\nHALT;"), None);
+    }
+
+    #[test]
+    fn data_sections() {
+        let src = "LDR A, value, D;\nHALT;\n.data;\n.db 7;\n:value\n.db 9, 8;\n.code;\n:end\nHALT;";
+        let tokens: Vec<Token> = tokenise(src, "test.rsm");
+        let labels: HashMap<String, u16> = define_labels(&tokens, false);
+        assert_eq!((labels["value"], labels["end"]), (1, 3));
+        let (rom, ram, _) = assemble(&tokens, &labels, false).unwrap();
+        assert_eq!(&rom[..4], &[0x0c, 0x01, 0xff, 0xff]);
+        assert_eq!(ram, vec![7, 9, 8]);
+
+        assert_eq!(assemble_source(".data;\nHALT;"), None);
+        assert_eq!(assemble_source(".data;\n.entry $0;\n.code;\nHALT;"), None);
+    }
+
+    #[test]
+    fn layout_directives() {
+        let rom = assemble_source("HALT;\n.org $10;\n.dw $1234, 5;\n.asciz \"a\";\n.align 4;\n.res 2;\nHALT;").unwrap();
+        assert_eq!(rom[0], 0xff);
+        assert_eq!(&rom[0x10..0x1b], &[0x12, 0x34, 0x00, 0x05, b'a', 0x00, 0x00, 0x00, 0x00, 0x00, 0xff]);
+
+        // regions may come in any order as long as they don't overlap
+        assert!(assemble_source(".org $20;\nHALT;\n.org $10;\nHALT;").is_some());
+        assert_eq!(assemble_source(".org $10;\nHALT;\nHALT;\n.org $11;\nHALT;"), None);
+        assert_eq!(assemble_source(".dw $10000;"), None);
+    }
+
+    // operand expressions only exist inside a statement
+    fn statement(src: &str) -> Vec<Token> {
+        let tokens: Vec<Token> = tokenise(&format!(".equ X, {};", src), "");
+        tokens[3..tokens.len() - 1].to_vec()
+    }
+
+    fn eval(src: &str) -> Result<i64, (&'static str, String, usize)> {
+        let labels: HashMap<String, u16> = [("loop".to_string(), 0x12)].iter().cloned().collect();
+        let constants: HashMap<String, i64> = [("SIZE".to_string(), 4)].iter().cloned().collect();
+        evaluate(&statement(src), &labels, &constants, 0x40, true)
+    }
+
+    // the code of a failed evaluation and the column in src its caret goes under
+    fn caret(src: &str) -> (&'static str, u16) {
+        let (code, _, at) = eval(src).err().unwrap();
+        (code, statement(src)[at].column - ".equ X, ".len() as u16)
+    }
+
+    #[test]
+    fn expression_values() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("#ff & %1010"), Ok(0x0a));
+        assert_eq!(eval("SIZE * 2 - 1"), Ok(7));
+        assert_eq!(eval("loop + 1"), Ok(0x13));
+        assert_eq!(eval("$ + 2"), Ok(0x42));
+        assert_eq!(eval("$10"), Ok(0x10));
+        assert_eq!(eval("HIGH(#1234) | LOW(#1234)"), Ok(0x36));
+        assert_eq!(eval("-1 << 4"), Ok(-16));
+        assert!(eval("1 + missing").is_err());
+        assert!(eval("(1 + 2").is_err());
+    }
+
+    #[test]
+    fn constants_and_label_operands() {
+        let rom = assemble_source(".equ TWO, 2;\nMOV A, TWO * 3;\nMOV B, value;\nMOV C, value+0;\nHALT;\n:value\n.db value, TWO;").unwrap();
+        assert_eq!(&rom[..10], &[0x04, 0x06, 0x05, 0x07, 0x06, 0x07, 0xff, 0x07, 0x02, 0x00]);
+
+        // a label past the first page doesn't fit in a byte
+        assert_eq!(assemble_source(".db big;\n.org $100;\n:big\nHALT;"), None);
+        assert_eq!(assemble_source("MOV A, big;\n.org $100;\n:big\nHALT;"), None);
+        assert_eq!(assemble_source(".equ TWO, 2;\n.equ TWO, 3;\nHALT;"), None);
+
+        // without 16 bit addressing a branch target is a byte within its bank
+        let rom = assemble_source(".org $100;\nJMP there+1;\n:there\nHALT;\nHALT;").unwrap();
+        assert_eq!(rom[0x101], 0x03);
+    }
+
+    #[test]
+    fn macros() {
+        // labels in the body are local to each expansion, arguments can be expressions
+        let src = "twice 1, 2+3;\ntwice 4, 6;\nHALT;\n.macro twice a, b;\n:again\nMOV A, a;\nMOV B, b;\nJMP again;\n.endm;";
+        let rom = assemble_source(src).unwrap();
+        assert_eq!(&rom[..13], &[0x04, 0x01, 0x05, 0x05, 0x50, 0x00, 0x04, 0x04, 0x05, 0x06, 0x50, 0x06, 0xff]);
+
+        assert_eq!(assemble_source(".macro one a;\nMOV A, a;\n.endm;\none 1, 2;"), None);
+        assert_eq!(assemble_source(".macro one a;\nMOV A, a;"), None);
+        assert_eq!(assemble_source(".macro loop;\nloop;\n.endm;\nloop;"), None);
+        assert_eq!(assemble_source("HALT;\n.endm;"), None);
+    }
+
+    #[test]
+    fn includes() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("assembler-include-{}", std::process::id()));
+        let lib: PathBuf = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(dir.join("main.rsm"), ".include \"values.rsm\";\nHALT;").unwrap();
+        std::fs::write(lib.join("values.rsm"), "MOV A, 7;").unwrap();
+        std::fs::write(dir.join("cycle.rsm"), ".include \"cycle.rsm\";").unwrap();
+
+        // names are looked up next to the including file, then in the -I directories
+        let tokens: Vec<Token> = load_source(&dir.join("main.rsm"), std::slice::from_ref(&lib), &mut Vec::new()).unwrap();
+        let text: Vec<&str> = tokens.iter().map(|t| &t.identifier[..]).collect();
+        assert_eq!(text, vec!["MOV", "A", ",", "7", ";", "HALT", ";"]);
+        assert_eq!(tokens[0].file, lib.join("values.rsm").display().to_string());
+
+        assert!(load_source(&dir.join("main.rsm"), &[], &mut Vec::new()).is_none());
+        assert!(load_source(&dir.join("cycle.rsm"), &[], &mut Vec::new()).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relocatable_objects() {
+        let tokens: Vec<Token> = tokenise(".extern print;\n.global main;\n:main\nJMP print;\nJMP main;\nHALT;\n.data;\n.db 1;", "test.rsm");
+        let object: robj::Object = assemble_object(&tokens).unwrap();
+        assert_eq!(object.code, vec![0x50, 0x00, 0x50, 0x00, 0xff]);
+        assert_eq!(object.data, vec![0x01]);
+        assert_eq!(object.imports, vec!["print".to_string()]);
+        assert_eq!(object.exports, vec![("main".to_string(), robj::SECTION_CODE, 0)]);
+        let relocations: Vec<(u16, u8)> = object.relocations.iter().map(|r| (r.offset, r.target)).collect();
+        assert_eq!(relocations, vec![(1, robj::TARGET_IMPORT), (3, robj::TARGET_CODE)]);
+
+        assert!(assemble_object(&tokenise(".org $10;\nHALT;", "test.rsm")).is_none());
+        assert!(assemble_object(&tokenise(".global nothing;\nHALT;", "test.rsm")).is_none());
+
+        // the extra layouts are assembled quietly, so a warning is only reported once
+        let warnings: usize = diagnostics::warning_count();
+        assert!(assemble_object(&tokenise(".extern print;\nJMP print;\nHALT;", "test.rsm")).is_some());
+        assert_eq!(diagnostics::warning_count(), warnings + 1);
+        // moving the data up a byte runs it into the stack
+        assert!(assemble_object(&tokenise("HALT;\n.data;\n.res 128;", "test.rsm")).is_none());
+    }
+
+    #[test]
+    fn conditionals() {
+        let src = ".equ DEBUG, 1;\n.if DEBUG == 1;\nMOV A, 1;\n.else;\nMOV A, 2;\n.endif;\n.ifdef MISSING;\nMOV B, 3;\n.endif;\n.ifndef MISSING;\nMOV B, 4;\n.endif;\nHALT;";
+        assert_eq!(&assemble_source(src).unwrap()[..5], &[0x04, 0x01, 0x05, 0x04, 0xff]);
+
+        // a condition in a macro body tests the arguments of each call
+        let src = "pick 0;\npick 1;\nHALT;\n.macro pick n;\n.if n;\nMOV A, 1;\n.else;\nMOV B, 2;\n.endif;\n.endm;";
+        assert_eq!(&assemble_source(src).unwrap()[..5], &[0x05, 0x02, 0x04, 0x01, 0xff]);
+
+        assert_eq!(assemble_source(".if 1;\nHALT;"), None);
+        assert_eq!(assemble_source(".else;\nHALT;"), None);
+        assert_eq!(assemble_source(".if 1;\n.else;\n.else;\n.endif;\nHALT;"), None);
+    }
+
+    #[test]
+    fn local_and_anonymous_labels() {
+        // each global label has its own .loop, - and + find the nearest anonymous label
+        let src = ":first\n:.loop\nJMP .loop;\n:second\n:.loop\nJMP .loop;\n:-\nJNZ -;\n:-\nJMP +;\nJMP --;\n:+\nHALT;";
+        let rom = assemble_source(src).unwrap();
+        assert_eq!(&rom[..13], &[0x50, 0x00, 0x50, 0x02, 0x70, 0x04, 0x50, 0x0a, 0x50, 0x04, 0xff, 0x00, 0x00]);
+
+        assert_eq!(assemble_source(":twice\n:twice\nHALT;"), None);
+        assert_eq!(assemble_source(":.loop\nHALT;"), None);
+        assert_eq!(assemble_source("JMP +;\nHALT;"), None);
+    }
+
+    #[test]
+    fn expression_error_carets() {
+        assert_eq!(caret("1 + missing"), ("E0006", 4));
+        assert_eq!(caret("4 / (SIZE - 4)"), ("E0005", 2));
+        assert_eq!(caret("(1 + 2"), ("E0003", 5));
+        assert_eq!(caret("1 + * 2"), ("E0003", 4));
+        assert_eq!(caret("1 2"), ("E0003", 2));
+        assert_eq!(caret("LOW 1"), ("E0003", 4));
+        assert_eq!(caret("2 + %102"), ("E0005", 5));
+        // a forward reference is only an error in the final pass
+        assert_eq!(evaluate(&statement("later"), &HashMap::new(), &HashMap::new(), 0, false), Ok(0));
+    }
+
+    #[test]
+    fn macro_call_sites() {
+        let tokens: Vec<Token> = expand_macros(tokenise("HALT;\n  outer;\n.macro inner;\nHALT;\n.endm;\n.macro outer;\ninner;\n.endm;", "test.rsm")).unwrap();
+        // innermost call first, each with the file, line and column it was made at
+        assert_eq!(tokens[2].identifier, "HALT");
+        assert_eq!(tokens[2].expansion, vec![("inner".to_string(), "test.rsm".to_string(), 6, 0), ("outer".to_string(), "test.rsm".to_string(), 1, 2)]);
+    }
+
+    #[test]
+    fn lexer() {
+        let tokens: Vec<Token> = tokenise("// comment\n:loop MOV A, #2a / 2; // done\n  .string \"a b\";\n", "test.rsm");
+        let text: Vec<&str> = tokens.iter().map(|t| &t.identifier[..]).collect();
+        assert_eq!(text, vec![":", "loop", "MOV", "A", ",", "#", "2a", "/", "2", ";", ".string", "\"a b", ";"]);
+        let kinds: Vec<Kind> = tokens.iter().map(|t| t.kind).collect();
+        assert!(kinds == vec![Kind::Punct, Kind::Symbol, Kind::Mnemonic, Kind::Register, Kind::Punct, Kind::Punct, Kind::Number { radix: 16 }, Kind::Punct, Kind::Number { radix: 10 }, Kind::Punct, Kind::Directive, Kind::String, Kind::Punct]);
+
+        // line and column count from 0, spans are byte offsets into the source and cover the closing quote
+        assert_eq!((tokens[6].line, tokens[6].column, tokens[6].start, tokens[6].end), (1, 14, 25, 27));
+        assert_eq!((tokens[11].line, tokens[11].column, tokens[11].start, tokens[11].end), (2, 10, 51, 56));
+        assert_eq!(tokens[11].file, "test.rsm");
+    }
+
+    #[test]
+    fn code_round_trips_through_the_disassembler() {
+        let rom: Vec<u8> = assemble_source(include_str!("../../code.rsm")).unwrap();
+        assert_eq!(&rom[..], &include_bytes!("../../code.rbin")[..]);
+        let listing: String = disassembler::disassemble(&rom, false, &[]);
+        assert_eq!(assemble_source(&listing), Some(rom));
+    }
+
+    // every opcode once with made up operand bytes, in one flat 16 bit image
+    #[test]
+    fn every_opcode_round_trips_through_the_disassembler() {
+        let mut rom: Vec<u8> = Vec::new();
+        for instruction in isa::INSTRUCTIONS.iter() {
+            rom.push(instruction.opcode);
+            for n in 1..instruction.size(true) {
+                rom.push(n as u8);
+            }
+        }
+        let listing: String = disassembler::disassemble(&rom, true, &[]);
+        let back: Vec<u8> = assemble_source(&listing).unwrap();
+        assert_eq!(&back[..rom.len()], &rom[..]);
+        assert!(back[rom.len()..].iter().all(|b| *b == 0x00));
+    }
+
+    // the gap before far is skipped with .org rather than listed as instructions
+    #[test]
+    fn wide_gaps_round_trip_through_the_disassembler() {
+        let rom: Vec<u8> = assemble_source(".wide;\nJMP far;\n.org $0120;\n:far\nHALT;").unwrap();
+        let listing: String = disassembler::disassemble(&rom, true, &[("far".to_string(), 0x120)]);
+        assert!(listing.contains("    .org $0120;\n:far\n"));
+        assert_eq!(listing.lines().count(), 6);
+        let back: Vec<u8> = assemble_source(&listing).unwrap();
+        assert_eq!(&back[..0x121], &rom[..0x121]);
+        assert!(back[0x121..].iter().all(|b| *b == 0x00));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::env;
+
+#[path = "../isa.rs"]
+mod isa;
+#[path = "../rbin.rs"]
+mod rbin;
+
+// zero bytes in a row before the disassembler treats them as unused space
+const ZERO_RUN: usize = 16;
+
+// operand bytes starting at at, high byte first
+fn read_value(rom: &[u8], at: usize, size: usize) -> usize {
+    let mut value: usize = 0;
+    for n in 0..size {
+        value = (value << 8) | rom[at + n] as usize;
+    }
+    value
+}
+
+// the instruction at address as the assembler would take it back, None when the byte isn't an
+// opcode or its operands run past the end of the image
+fn disassemble_one(rom: &[u8], address: usize, wide: bool) -> Option<(usize, String)> {
+    let instruction: &isa::Instruction = isa::decode(rom[address])?;
+    let size: usize = instruction.size(wide);
+    if address + size > rom.len() {
+        return None;
+    }
+    let mut at: usize = address + 1;
+    let mut operands: Vec<String> = Vec::new();
+    for operand in instruction.operand_list() {
+        let size: usize = isa::operand_size(operand, wide);
+        let value: usize = read_value(rom, at, size);
+        operands.push(match operand {
+            "#imm" => format!("#{:02x}", value),
+            "$addr" | "addr" if wide => format!("${:04x}", value),
+            "$addr" | "addr" => format!("${:02x}", value),
+            "#word" => format!("#{:04x}", value),
+            "word" => format!("${:04x}", value),
+            // stored relative to the next instruction, shown as the address it lands on
+            "rel" => format!("${:02x}", (address as i64 + 2 + value as u8 as i8 as i64) as usize),
+            "[SP+off]" | "[FP+off]" => {
+                let offset: i8 = value as u8 as i8;
+                if offset < 0 {
+                    format!("[{}-{}]", &operand[1..3], -(offset as i16))
+                } else {
+                    format!("[{}+{}]", &operand[1..3], offset)
+                }
+            },
+            _ => operand.to_string(),
+        });
+        at += size;
+    }
+    if operands.is_empty() {
+        Some((size, instruction.mnemonic.to_string()))
+    } else {
+        Some((size, format!("{} {}", instruction.mnemonic, operands.join(", "))))
+    }
+}
+
+// a linear sweep over each bank, data mixed in with the code comes out as instructions. Addresses
+// and bytes go in a comment so the listing assembles back to the same image.
+pub fn disassemble(rom: &[u8], wide: bool, symbols: &[(String, u16)]) -> String {
+    let mut listing: String = String::new();
+    if wide {
+        listing.push_str("    .wide;\n");
+    }
+    // with 16 bit addressing the ROM is one flat space, otherwise an instruction can't cross a bank
+    let bank_size: usize = if wide { rom.len().max(1) } else { 256 };
+    let mut start: usize = 0;
+    while start < rom.len() {
+        let bank_end: usize = (start + bank_size).min(rom.len());
+        let bank: &[u8] = &rom[..bank_end];
+        // trailing zeroes are the unused rest of the bank
+        let end: usize = bank[start..].iter().rposition(|b| *b != 0x00).map_or(start, |p| start + p + 1);
+        if end > start && start > 0 {
+            listing.push_str(&format!("    .org ${:04x};\n", start));
+        }
+        let mut address: usize = start;
+        while address < end {
+            for (name, _) in symbols.iter().filter(|s| s.1 as usize == address) {
+                listing.push_str(&format!(":{}\n", name));
+            }
+            // a long run of zeroes is a gap between regions rather than MOV A, $0000 over and over,
+            // it stops short of any label inside it
+            let zeroes: usize = bank[address..end].iter().position(|b| *b != 0x00).unwrap_or(end - address);
+            let gap: usize = symbols.iter().map(|s| s.1 as usize).filter(|a| *a > address && *a < address + zeroes).min().unwrap_or(address + zeroes) - address;
+            if gap >= ZERO_RUN {
+                listing.push_str(&format!("    // {:04x}-{:04x} zero\n", address, address + gap - 1));
+                address += gap;
+                listing.push_str(&format!("    .org ${:04x};\n", address));
+                continue;
+            }
+            let (size, text) = match disassemble_one(bank, address, wide) {
+                Some(x) => x,
+                None => (1, format!(".db #{:02x}", rom[address])),
+            };
+            let bytes: Vec<String> = rom[address..address + size].iter().map(|b| format!("{:02x}", b)).collect();
+            listing.push_str(&format!("    {:<24} // {:04x}  {}\n", format!("{};", text), address, bytes.join(" ")));
+            address += size;
+        }
+        if end > start && end < bank_end {
+            listing.push_str(&format!("    // {:04x}-{:04x} zero\n", end, bank_end - 1));
+        }
+        start = bank_end;
+    }
+    listing
+}
+
+// micro steps including the fetch, taken/not taken for conditional instructions
+fn step_count(instruction: &isa::Instruction, wide: bool) -> String {
+    let taken: usize = 2 + instruction.wide_steps.filter(|_| wide).unwrap_or(instruction.steps).len();
+    let otherwise: usize = 2 + instruction.wide_otherwise.filter(|_| wide).unwrap_or(instruction.otherwise).len();
+    if instruction.condition == isa::Condition::Always {
+        taken.to_string()
+    } else {
+        format!("{}/{}", taken, otherwise)
+    }
+}
+
+// the instruction set as the table has it - what every opcode is written as, its size and micro
+// steps in 8 and 16 bit mode
+fn reference() {
+    println!("opcode  instruction            size  wide  steps  wide   condition  summary");
+    for instruction in isa::INSTRUCTIONS.iter() {
+        let condition: &str = match instruction.condition {
+            isa::Condition::Always => "",
+            isa::Condition::Zero => "zero",
+            isa::Condition::NotZero => "not zero",
+            isa::Condition::Carry => "carry",
+            isa::Condition::NotCarry => "not carry",
+        };
+        println!("    {:02x}  {:<21}  {:>4}  {:>4}  {:<5}  {:<5}  {:<9}  {}", instruction.opcode, instruction.syntax(), instruction.size(false), instruction.size(true), step_count(instruction, false), step_count(instruction, true), condition, instruction.summary);
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--reference") {
+        reference();
+        return Ok(());
+    }
+    let filename: &String = match args[1..].iter().find(|arg| !arg.starts_with("--")) {
+        Some(f) => f,
+        None => {
+            println!("Usage: disassembler [--wide] [--reference] <image>");
+            return Ok(());
+        },
+    };
+
+    let mut data: Vec<u8> = Vec::new();
+    File::open(filename)?.read_to_end(&mut data)?;
+    // a container knows its addressing mode, a bare ROM dump needs --wide
+    let image: rbin::Image = if rbin::is_container(&data) {
+        match rbin::decode(&data) {
+            Ok(image) => image,
+            Err(e) => {
+                println!("Error: {}: {}", filename, e);
+                std::process::exit(1);
+            },
+        }
+    } else {
+        rbin::new_image(data, args.iter().any(|arg| arg == "--wide"))
+    };
+    if image.isa != rbin::ISA_VERSION {
+        println!("// built for ISA version {}, this disassembler reads {}", image.isa, rbin::ISA_VERSION);
+    }
+    print!("{}", disassemble(&image.rom, image.wide, &image.symbols));
+    Ok(())
+}
@@ -0,0 +1,843 @@
+// Instruction set - every opcode with the way it is written and the microcode that runs it. The cpu
+// builds its eeprom from this table, the assembler encodes with it and the disassembler decodes with
+// it, so an instruction only exists when all of them agree on it.
+//
+// operands are written as the assembler takes them. The placeholders are the bytes that follow the
+// opcode, in the order they are written:
+//   #imm      immediate byte
+//   $addr     RAM address - one byte, two (high, low) with 16 bit addressing
+//   addr      branch target or ROM address, a label or a number - sized like $addr
+//   #word     16 bit immediate (high, low)
+//   word      far target with the bank in the high byte (high, low)
+//   rel       branch target, stored as a signed offset from the next instruction
+//   off       signed byte added to the stack or frame pointer, as in [SP+off]
+// Registers, CD and [CD] are part of the opcode.
+//
+// Every instruction starts with the two fetch steps, the table holds the control words from micro
+// step 2 on. Steps past the end of the list are idle.
+
+#![allow(dead_code)]
+
+pub const A_IN: u32 = 0x1;
+pub const B_IN: u32 = 0x2;
+pub const C_IN: u32 = 0x3;
+pub const IR_IN: u32 = 0x4;
+pub const MDR_IN: u32 = 0x5;
+pub const MAR_IN: u32 = 0x6;
+pub const PC_IN: u32 = 0x7;
+
+pub const A_OUT: u32 = 0x1 << 3;
+pub const B_OUT: u32 = 0x2 << 3;
+pub const C_OUT: u32 = 0x3 << 3;
+pub const D_OUT: u32 = 0x4 << 3;
+pub const ROM_OUT: u32 = 0x5 << 3;
+pub const RAM_OUT: u32 = 0x6 << 3;
+pub const MDR_OUT: u32 = 0x7 << 3;
+
+pub const ADD_OUT: u32 = 0x1 << 6;
+pub const AND_OUT: u32 = 0x2 << 6;
+pub const NOT_OUT: u32 = 0x3 << 6;
+pub const OR_OUT: u32 = 0x4 << 6;
+pub const XOR_OUT: u32 = 0x5 << 6;
+pub const PC_OUT: u32 = 0x6 << 6;
+pub const RAM_IN: u32 = 0x7 << 6;
+
+pub const SP_OUT: u32 = 0x1 << 9;
+pub const FLAGS_IN: u32 = 0x2 << 9;
+pub const SUB: u32 = 0x3 << 9;
+pub const PC_INC: u32 = 0x4 << 9;
+pub const D_IN: u32 = 0x5 << 9;
+pub const D_INC: u32 = 0x6 << 9;
+pub const D_DEC: u32 = 0x7 << 9;
+
+pub const HLT: u32 = 0x1 << 12;
+pub const SP_INC: u32 = 0x1 << 13;
+pub const SP_DEC: u32 = 0x1 << 14;
+pub const EXT: u32 = 0x1 << 15; // routes the ALU select lines to the multiply/divide unit
+
+pub const B_INC: u32 = 0x1 << 16;
+pub const C_INC: u32 = 0x1 << 17;
+pub const PC_DEC: u32 = 0x1 << 18; // steps the PC back onto the current opcode so it runs again
+pub const HI_IN: u32 = 0x1 << 19; // bus in to the address high byte latch
+pub const WIDE: u32 = 0x1 << 20; // MAR/PC loads take their high byte from the latch, PC out drives its high byte
+pub const CD_OUT: u32 = 0x1 << 21; // C:D register pair out onto the address bus
+pub const CD_INC: u32 = 0x1 << 22;
+pub const CD_DEC: u32 = 0x1 << 23;
+pub const BANK_IN: u32 = 0x1 << 24; // address high byte latch in to the ROM bank register
+pub const BANK_OUT: u32 = 0x1 << 25; // ROM bank register out onto the bus
+pub const RAM_BANK_IN: u32 = 0x1 << 26;
+pub const FLAGS_OUT: u32 = 0x1 << 27; // flags register out onto the bus
+pub const FP_IN: u32 = 0x1 << 28; // bus in to the frame pointer
+pub const FP_OUT: u32 = 0x1 << 29; // frame pointer out onto the bus
+pub const SP_IN: u32 = 0x1 << 30; // bus in to the stack pointer
+pub const TRAP: u32 = 0x1 << 31; // hands the service number in MDR to the host trap hook
+
+pub const MUL_LO_OUT: u32 = ADD_OUT | EXT;
+pub const MUL_HI_OUT: u32 = AND_OUT | EXT;
+pub const DIV_OUT: u32 = OR_OUT | EXT;
+pub const MOD_OUT: u32 = XOR_OUT | EXT;
+
+// the flags an instruction looks at - bit 0 of the flags register is carry, bit 1 zero
+#[derive(Clone, Copy, PartialEq)]
+pub enum Condition {
+    Always,
+    Zero,
+    NotZero,
+    Carry,
+    NotCarry,
+}
+
+impl Condition {
+    pub fn holds(&self, flags: usize) -> bool {
+        match self {
+            Condition::Always => true,
+            Condition::Zero => flags & 0x02 == 0x02,
+            Condition::NotZero => flags & 0x02 == 0x00,
+            Condition::Carry => flags & 0x01 == 0x01,
+            Condition::NotCarry => flags & 0x01 == 0x00,
+        }
+    }
+}
+
+pub struct Instruction {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: &'static str,
+    pub summary: &'static str,
+    // steps run when the condition holds, otherwise the other list does
+    pub condition: Condition,
+    pub steps: &'static [u32],
+    pub otherwise: &'static [u32],
+    // 16 bit addressing reads two address bytes, None when the 8 bit microcode does for both
+    pub wide_steps: Option<&'static [u32]>,
+    pub wide_otherwise: Option<&'static [u32]>,
+}
+
+const PLAIN: Instruction = Instruction {
+    opcode: 0x00,
+    mnemonic: "",
+    operands: "",
+    summary: "",
+    condition: Condition::Always,
+    steps: &[],
+    otherwise: &[],
+    wide_steps: None,
+    wide_otherwise: None,
+};
+
+impl Instruction {
+    pub fn operand_list(&self) -> Vec<&'static str> {
+        if self.operands.is_empty() {
+            Vec::new()
+        } else {
+            self.operands.split(", ").collect()
+        }
+    }
+
+    // opcode and operand bytes
+    pub fn size(&self, wide: bool) -> usize {
+        1 + self.operand_list().iter().map(|x| operand_size(x, wide)).sum::<usize>()
+    }
+
+    pub fn microcode(&self, flags: usize, wide: bool) -> &'static [u32] {
+        let taken: bool = self.condition.holds(flags);
+        match (taken, wide) {
+            (true, true) => self.wide_steps.unwrap_or(self.steps),
+            (false, true) => self.wide_otherwise.unwrap_or(self.otherwise),
+            (true, false) => self.steps,
+            (false, false) => self.otherwise,
+        }
+    }
+
+    // as the assembler would write it, e.g. "MOV A, #imm"
+    pub fn syntax(&self) -> String {
+        if self.operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+pub fn operand_size(operand: &str, wide: bool) -> usize {
+    match operand {
+        "$addr" | "addr" => if wide { 2 } else { 1 },
+        "#word" | "word" => 2,
+        "#imm" | "rel" | "[SP+off]" | "[FP+off]" => 1,
+        _ => 0,
+    }
+}
+
+pub fn decode(opcode: u8) -> Option<&'static Instruction> {
+    INSTRUCTIONS.iter().find(|x| x.opcode == opcode)
+}
+
+pub fn is_mnemonic(word: &str) -> bool {
+    INSTRUCTIONS.iter().any(|x| x.mnemonic == word)
+}
+
+pub static INSTRUCTIONS: &[Instruction] = &[
+    Instruction {
+        opcode: 0x00, mnemonic: "MOV", operands: "A, $addr", summary: "LOAD A",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, MDR_OUT | A_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, MDR_OUT | A_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x01, mnemonic: "MOV", operands: "B, $addr", summary: "LOAD B",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, MDR_OUT | B_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, MDR_OUT | B_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x02, mnemonic: "MOV", operands: "C, $addr", summary: "LOAD C",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, MDR_OUT | C_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, MDR_OUT | C_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x03, mnemonic: "MOV", operands: "D, $addr", summary: "LOAD D",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, MDR_OUT | D_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, MDR_OUT | D_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x04, mnemonic: "MOV", operands: "A, #imm", summary: "LOAD A IMMEDIATE",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | A_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x05, mnemonic: "MOV", operands: "B, #imm", summary: "LOAD B IMMEDIATE",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x06, mnemonic: "MOV", operands: "C, #imm", summary: "LOAD C IMMEDIATE",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | C_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x07, mnemonic: "MOV", operands: "D, #imm", summary: "LOAD D IMMEDIATE",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | D_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x08, mnemonic: "LDR", operands: "A, addr", summary: "LOAD A FROM ROM",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, MDR_OUT | A_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ROM_OUT | MDR_IN, MDR_OUT | A_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x09, mnemonic: "LDR", operands: "B, addr", summary: "LOAD B FROM ROM",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, MDR_OUT | B_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ROM_OUT | MDR_IN, MDR_OUT | B_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0A, mnemonic: "LDR", operands: "C, addr", summary: "LOAD C FROM ROM",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, MDR_OUT | C_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ROM_OUT | MDR_IN, MDR_OUT | C_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0B, mnemonic: "LDR", operands: "D, addr", summary: "LOAD D FROM ROM",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, MDR_OUT | D_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ROM_OUT | MDR_IN, MDR_OUT | D_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0C, mnemonic: "LDR", operands: "A, addr, D", summary: "LOAD A FROM ROM (indexed by D)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | MAR_IN, ROM_OUT | MDR_IN, MDR_OUT | A_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ADD_OUT | MAR_IN | WIDE, ROM_OUT | MDR_IN, MDR_OUT | A_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0D, mnemonic: "LDR", operands: "B, addr, D", summary: "LOAD B FROM ROM (indexed by D)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | MAR_IN, ROM_OUT | MDR_IN, MDR_OUT | B_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ADD_OUT | MAR_IN | WIDE, ROM_OUT | MDR_IN, MDR_OUT | B_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0E, mnemonic: "LDR", operands: "C, addr, D", summary: "LOAD C FROM ROM (indexed by D)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | MAR_IN, ROM_OUT | MDR_IN, MDR_OUT | C_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ADD_OUT | MAR_IN | WIDE, ROM_OUT | MDR_IN, MDR_OUT | C_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x0F, mnemonic: "LDR", operands: "D, addr, D", summary: "LOAD D FROM ROM (indexed by D)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | MAR_IN, ROM_OUT | MDR_IN, MDR_OUT | D_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, ADD_OUT | MAR_IN | WIDE, ROM_OUT | MDR_IN, MDR_OUT | D_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x10, mnemonic: "STR", operands: "A, $addr", summary: "STORE A",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, A_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, A_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x11, mnemonic: "STR", operands: "B, $addr", summary: "STORE B",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, B_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, B_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x12, mnemonic: "STR", operands: "C, $addr", summary: "STORE C",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, C_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, C_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x13, mnemonic: "STR", operands: "D, $addr", summary: "STORE D",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, D_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, D_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x14, mnemonic: "STR", operands: "#imm, $addr", summary: "STORE IMMEDIATE",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, MDR_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, MDR_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x18, mnemonic: "MOV", operands: "A, [SP+off]", summary: "LOAD A (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x19, mnemonic: "MOV", operands: "B, [SP+off]", summary: "LOAD B (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | B_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1A, mnemonic: "MOV", operands: "C, [SP+off]", summary: "LOAD C (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | C_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1B, mnemonic: "MOV", operands: "D, [SP+off]", summary: "LOAD D (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | D_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1C, mnemonic: "STR", operands: "A, [SP+off]", summary: "STORE A (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, A_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1D, mnemonic: "STR", operands: "B, [SP+off]", summary: "STORE B (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, B_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1E, mnemonic: "STR", operands: "C, [SP+off]", summary: "STORE C (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, C_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x1F, mnemonic: "STR", operands: "D, [SP+off]", summary: "STORE D (SP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | MAR_IN, D_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x20, mnemonic: "PUSH", operands: "A", summary: "PUSH (from register A)",
+        steps: &[SP_OUT | MAR_IN, A_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x21, mnemonic: "PUSH", operands: "B", summary: "PUSH (from register B)",
+        steps: &[SP_OUT | MAR_IN, B_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x22, mnemonic: "PUSH", operands: "C", summary: "PUSH (from register C)",
+        steps: &[SP_OUT | MAR_IN, C_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x23, mnemonic: "PUSH", operands: "D", summary: "PUSH (from register D)",
+        steps: &[SP_OUT | MAR_IN, D_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x24, mnemonic: "PUSH", operands: "#imm", summary: "PUSH (immediate)",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MDR_IN, SP_OUT | MAR_IN, MDR_OUT | RAM_IN | SP_INC, PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x25, mnemonic: "PUSHF", operands: "", summary: "PUSH (flags)",
+        steps: &[SP_OUT | MAR_IN, FLAGS_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x26, mnemonic: "PUSHALL", operands: "", summary: "PUSH ALL (A, B, C, D then flags)",
+        steps: &[SP_OUT | MAR_IN, A_OUT | RAM_IN | SP_INC, SP_OUT | MAR_IN, B_OUT | RAM_IN | SP_INC, SP_OUT | MAR_IN, C_OUT | RAM_IN | SP_INC, SP_OUT | MAR_IN, D_OUT | RAM_IN | SP_INC, SP_OUT | MAR_IN, FLAGS_OUT | RAM_IN | SP_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x27, mnemonic: "ENTER", operands: "#imm", summary: "ENTER (push FP, FP = SP, reserve locals)",
+        steps: &[SP_OUT | MAR_IN, FP_OUT | RAM_IN | SP_INC, SP_OUT | FP_IN, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, SP_OUT | ADD_OUT | SP_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x28, mnemonic: "MOV", operands: "A, [FP+off]", summary: "LOAD A (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x29, mnemonic: "MOV", operands: "B, [FP+off]", summary: "LOAD B (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | B_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2A, mnemonic: "MOV", operands: "C, [FP+off]", summary: "LOAD C (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | C_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2B, mnemonic: "MOV", operands: "D, [FP+off]", summary: "LOAD D (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | D_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2C, mnemonic: "STR", operands: "A, [FP+off]", summary: "STORE A (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, A_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2D, mnemonic: "STR", operands: "B, [FP+off]", summary: "STORE B (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, B_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2E, mnemonic: "STR", operands: "C, [FP+off]", summary: "STORE C (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, C_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x2F, mnemonic: "STR", operands: "D, [FP+off]", summary: "STORE D (FP relative)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, FP_OUT | ADD_OUT | MAR_IN, D_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x30, mnemonic: "POP", operands: "A", summary: "POP (to register A)",
+        steps: &[RAM_OUT | A_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x31, mnemonic: "POP", operands: "B", summary: "POP (to register B)",
+        steps: &[RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x32, mnemonic: "POP", operands: "C", summary: "POP (to register C)",
+        steps: &[RAM_OUT | C_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x33, mnemonic: "POP", operands: "D", summary: "POP (to register D)",
+        steps: &[RAM_OUT | D_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x35, mnemonic: "POPF", operands: "", summary: "POP (to flags)",
+        steps: &[RAM_OUT | FLAGS_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x36, mnemonic: "POPALL", operands: "", summary: "POP ALL (flags then D, C, B, A)",
+        steps: &[RAM_OUT | FLAGS_IN | SP_DEC, RAM_OUT | D_IN | SP_DEC, RAM_OUT | C_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC, RAM_OUT | A_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x37, mnemonic: "LEAVE", operands: "", summary: "LEAVE (SP = FP, pop FP)",
+        steps: &[FP_OUT | SP_IN, RAM_OUT | FP_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x38, mnemonic: "MOV", operands: "CD, #word", summary: "LOAD C:D PAIR IMMEDIATE (high byte in C, low byte in D)",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | C_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | D_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x39, mnemonic: "INC", operands: "CD", summary: "INC C:D PAIR",
+        steps: &[CD_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x3A, mnemonic: "DEC", operands: "CD", summary: "DEC C:D PAIR",
+        steps: &[CD_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x3C, mnemonic: "MOV", operands: "A, [CD]", summary: "LOAD A INDIRECT FROM [C:D]",
+        steps: &[CD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x3D, mnemonic: "MOV", operands: "B, [CD]", summary: "LOAD B INDIRECT FROM [C:D]",
+        steps: &[CD_OUT | MAR_IN, RAM_OUT | MDR_IN, MDR_OUT | B_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x3E, mnemonic: "STR", operands: "A, [CD]", summary: "STORE A INDIRECT TO [C:D]",
+        steps: &[CD_OUT | MAR_IN, A_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x3F, mnemonic: "STR", operands: "B, [CD]", summary: "STORE B INDIRECT TO [C:D]",
+        steps: &[CD_OUT | MAR_IN, B_OUT | RAM_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x41, mnemonic: "SWP", operands: "A, B", summary: "SWAP A with B",
+        steps: &[A_OUT | RAM_IN | SP_INC, B_OUT | RAM_IN | SP_INC, RAM_OUT | A_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x42, mnemonic: "SWP", operands: "A, C", summary: "SWAP A with C",
+        steps: &[A_OUT | RAM_IN | SP_INC, C_OUT | RAM_IN | SP_INC, RAM_OUT | A_IN | SP_DEC, RAM_OUT | C_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x43, mnemonic: "SWP", operands: "A, D", summary: "SWAP A with D",
+        steps: &[A_OUT | RAM_IN | SP_INC, D_OUT | RAM_IN | SP_INC, RAM_OUT | A_IN | SP_DEC, RAM_OUT | D_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x44, mnemonic: "SWP", operands: "B, A", summary: "SWAP B with A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | RAM_IN | SP_INC, RAM_OUT | B_IN | SP_DEC, RAM_OUT | A_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x46, mnemonic: "SWP", operands: "B, C", summary: "SWAP B with C",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | RAM_IN | SP_INC, RAM_OUT | B_IN | SP_DEC, RAM_OUT | C_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x47, mnemonic: "SWP", operands: "B, D", summary: "SWAP B with D",
+        steps: &[B_OUT | RAM_IN | SP_INC, D_OUT | RAM_IN | SP_INC, RAM_OUT | B_IN | SP_DEC, RAM_OUT | D_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x48, mnemonic: "SWP", operands: "C, A", summary: "SWAP C with A",
+        steps: &[C_OUT | RAM_IN | SP_INC, A_OUT | RAM_IN | SP_INC, RAM_OUT | C_IN | SP_DEC, RAM_OUT | A_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x49, mnemonic: "SWP", operands: "C, B", summary: "SWAP C with B",
+        steps: &[C_OUT | RAM_IN | SP_INC, B_OUT | RAM_IN | SP_INC, RAM_OUT | C_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x4B, mnemonic: "SWP", operands: "C, D", summary: "SWAP C with D",
+        steps: &[C_OUT | RAM_IN | SP_INC, D_OUT | RAM_IN | SP_INC, RAM_OUT | C_IN | SP_DEC, RAM_OUT | D_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x50, mnemonic: "JMP", operands: "addr", summary: "JUMP",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x51, mnemonic: "FJMP", operands: "word", summary: "FAR JUMP (bank, address)",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | BANK_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x52, mnemonic: "JR", operands: "rel", summary: "RELATIVE JUMP (signed offset from the next instruction)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x60, mnemonic: "JEZ", operands: "addr", summary: "JUMP EQUAL ZERO",
+        condition: Condition::Zero,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC]),
+    },
+    Instruction {
+        opcode: 0x62, mnemonic: "JRZ", operands: "rel", summary: "RELATIVE JUMP EQUAL ZERO",
+        condition: Condition::Zero,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x63, mnemonic: "JC", operands: "addr", summary: "JUMP CARRY",
+        condition: Condition::Carry,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC]),
+    },
+    Instruction {
+        opcode: 0x70, mnemonic: "JNZ", operands: "addr", summary: "JUMP NOT EQUAL ZERO",
+        condition: Condition::NotZero,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC]),
+    },
+    Instruction {
+        opcode: 0x72, mnemonic: "JRNZ", operands: "rel", summary: "RELATIVE JUMP NOT EQUAL ZERO",
+        condition: Condition::NotZero,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, ADD_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x73, mnemonic: "JNC", operands: "addr", summary: "JUMP NOT CARRY",
+        condition: Condition::NotCarry,
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_OUT | MAR_IN | PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC]),
+    },
+    Instruction {
+        opcode: 0x80, mnemonic: "CALL", operands: "addr", summary: "CALL",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN | PC_INC, PC_OUT | RAM_IN | SP_INC, ROM_OUT | PC_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, PC_OUT | RAM_IN | SP_INC | WIDE, MDR_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x81, mnemonic: "FCALL", operands: "word", summary: "FAR CALL (bank, address) - pushes the return address then the caller's bank",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, PC_OUT | RAM_IN | SP_INC | BANK_OUT, MDR_OUT | PC_IN | BANK_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x82, mnemonic: "CALLR", operands: "rel", summary: "RELATIVE CALL",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, ADD_OUT | PC_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, PC_OUT | RAM_IN | SP_INC | WIDE, ADD_OUT | PC_IN | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x83, mnemonic: "CALLZ", operands: "addr", summary: "CALL EQUAL ZERO",
+        condition: Condition::Zero,
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN | PC_INC, PC_OUT | RAM_IN | SP_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, PC_OUT | RAM_IN | SP_INC | WIDE, MDR_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_INC, PC_INC]),
+    },
+    Instruction {
+        opcode: 0x84, mnemonic: "CALLNZ", operands: "addr", summary: "CALL NOT EQUAL ZERO",
+        condition: Condition::NotZero,
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN | PC_INC, PC_OUT | RAM_IN | SP_INC, ROM_OUT | PC_IN],
+        otherwise: &[PC_INC],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, PC_OUT | RAM_IN | SP_INC, PC_OUT | RAM_IN | SP_INC | WIDE, MDR_OUT | PC_IN | WIDE]),
+        wide_otherwise: Some(&[PC_INC, PC_INC]),
+    },
+    Instruction {
+        opcode: 0x90, mnemonic: "RET", operands: "", summary: "RETURN",
+        steps: &[RAM_OUT | PC_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC],
+        wide_steps: Some(&[RAM_OUT | HI_IN | SP_DEC, RAM_OUT | PC_IN | SP_DEC | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x91, mnemonic: "FRET", operands: "", summary: "FAR RETURN",
+        steps: &[RAM_OUT | HI_IN | SP_DEC, RAM_OUT | PC_IN | SP_DEC | BANK_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x93, mnemonic: "RETZ", operands: "", summary: "RETURN EQUAL ZERO",
+        condition: Condition::Zero,
+        steps: &[RAM_OUT | PC_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC],
+        otherwise: &[],
+        wide_steps: Some(&[RAM_OUT | HI_IN | SP_DEC, RAM_OUT | PC_IN | SP_DEC | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0x94, mnemonic: "RETNZ", operands: "", summary: "RETURN NOT EQUAL ZERO",
+        condition: Condition::NotZero,
+        steps: &[RAM_OUT | PC_IN | SP_DEC, RAM_OUT | B_IN | SP_DEC],
+        otherwise: &[],
+        wide_steps: Some(&[RAM_OUT | HI_IN | SP_DEC, RAM_OUT | PC_IN | SP_DEC | WIDE]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA0, mnemonic: "OUT", operands: "", summary: "OUT",
+        steps: &[A_OUT | C_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA1, mnemonic: "DEC", operands: "", summary: "DEC D",
+        steps: &[D_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA2, mnemonic: "INC", operands: "", summary: "INC D",
+        steps: &[D_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA5, mnemonic: "DEC", operands: "$addr", summary: "DEC memory (MDR is fed to the adder with B held at 1)",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, ADD_OUT | MDR_IN | SUB, MDR_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, ADD_OUT | MDR_IN | SUB, MDR_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA6, mnemonic: "INC", operands: "$addr", summary: "INC memory",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC, RAM_OUT | MDR_IN, ADD_OUT | MDR_IN, MDR_OUT | RAM_IN],
+        wide_steps: Some(&[PC_OUT | MAR_IN, ROM_OUT | HI_IN | PC_INC, PC_OUT | MAR_IN, ROM_OUT | MAR_IN | PC_INC | WIDE, RAM_OUT | MDR_IN, ADD_OUT | MDR_IN, MDR_OUT | RAM_IN]),
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xA8, mnemonic: "RBANK", operands: "#imm", summary: "SELECT RAM BANK (immediate)",
+        steps: &[PC_OUT | MAR_IN, ROM_OUT | RAM_BANK_IN | PC_INC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xAA, mnemonic: "SYS", operands: "#imm", summary: "SYSTEM CALL (service number immediate)",
+        steps: &[PC_OUT | MAR_IN | PC_INC, ROM_OUT | MDR_IN, TRAP],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xB0, mnemonic: "ADD", operands: "A", summary: "ADD A to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | B_IN, ADD_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xB1, mnemonic: "ADD", operands: "B", summary: "ADD B to A",
+        steps: &[ADD_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xB2, mnemonic: "ADD", operands: "C", summary: "ADD C to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | B_IN, ADD_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xB8, mnemonic: "ADD", operands: "#imm", summary: "ADD imm to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC, ADD_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xC0, mnemonic: "SUB", operands: "A", summary: "SUB A to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | B_IN, ADD_OUT | A_IN | SUB, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xC1, mnemonic: "SUB", operands: "B", summary: "SUB B to A",
+        steps: &[ADD_OUT | A_IN | SUB],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xC2, mnemonic: "SUB", operands: "C", summary: "SUB C to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | B_IN, ADD_OUT | A_IN | SUB, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xC8, mnemonic: "SUB", operands: "#imm", summary: "SUB imm to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC, ADD_OUT | A_IN | SUB, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD0, mnemonic: "AND", operands: "A", summary: "AND A to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | B_IN, AND_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD1, mnemonic: "AND", operands: "B", summary: "AND B to A",
+        steps: &[AND_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD2, mnemonic: "AND", operands: "C", summary: "AND C to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | B_IN, AND_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD4, mnemonic: "OR", operands: "A", summary: "OR A to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | B_IN, OR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD5, mnemonic: "OR", operands: "B", summary: "OR B to A",
+        steps: &[OR_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD6, mnemonic: "OR", operands: "C", summary: "OR C to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | B_IN, OR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xD8, mnemonic: "AND", operands: "#imm", summary: "AND imm to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC, AND_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xDC, mnemonic: "OR", operands: "#imm", summary: "OR imm to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC, OR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xE0, mnemonic: "XOR", operands: "A", summary: "XOR A to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, A_OUT | B_IN, XOR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xE1, mnemonic: "XOR", operands: "B", summary: "XOR B to A",
+        steps: &[XOR_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xE2, mnemonic: "XOR", operands: "C", summary: "XOR C to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, C_OUT | B_IN, XOR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xE4, mnemonic: "NOT", operands: "", summary: "NOT A",
+        steps: &[NOT_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xE8, mnemonic: "XOR", operands: "#imm", summary: "XOR imm to A",
+        steps: &[B_OUT | RAM_IN | SP_INC, PC_OUT | MAR_IN, ROM_OUT | B_IN | PC_INC, XOR_OUT | A_IN, RAM_OUT | B_IN | SP_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF0, mnemonic: "NOP", operands: "", summary: "NO OPERATION (SWP of a register with itself)",
+        steps: &[],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF1, mnemonic: "MUL", operands: "", summary: "MUL A by B (high byte in A, low byte in B)",
+        steps: &[MUL_LO_OUT | MDR_IN, MUL_HI_OUT | A_IN, MDR_OUT | B_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF2, mnemonic: "DIV", operands: "", summary: "DIV A by B (quotient in A, remainder in B)",
+        steps: &[MOD_OUT | MDR_IN, DIV_OUT | A_IN, MDR_OUT | B_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF3, mnemonic: "MOD", operands: "", summary: "MOD A by B (remainder in A)",
+        steps: &[MOD_OUT | A_IN],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF4, mnemonic: "LDIR", operands: "", summary: "BLOCK COPY D bytes from [B] to [C] - repeats until D_DEC sets the zero flag, D = 0 copies 256",
+        condition: Condition::NotZero,
+        steps: &[B_OUT | MAR_IN, RAM_OUT | MDR_IN, C_OUT | MAR_IN, MDR_OUT | RAM_IN, B_INC | C_INC, D_DEC, PC_DEC],
+        otherwise: &[B_OUT | MAR_IN, RAM_OUT | MDR_IN, C_OUT | MAR_IN, MDR_OUT | RAM_IN, B_INC | C_INC, D_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xF5, mnemonic: "FILL", operands: "", summary: "BLOCK FILL D bytes at [C] with A - repeats until D_DEC sets the zero flag, D = 0 fills 256",
+        condition: Condition::NotZero,
+        steps: &[C_OUT | MAR_IN, A_OUT | RAM_IN, C_INC, D_DEC, PC_DEC],
+        otherwise: &[C_OUT | MAR_IN, A_OUT | RAM_IN, C_INC, D_DEC],
+        ..PLAIN
+    },
+    Instruction {
+        opcode: 0xFF, mnemonic: "HALT", operands: "", summary: "HALT",
+        steps: &[],
+        ..PLAIN
+    },
+];
@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::env;
+use std::path::Path;
+
+use std::collections::HashMap;
+
+#[path = "../rbin.rs"]
+mod rbin;
+#[path = "../robj.rs"]
+mod robj;
+
+const ROM_SIZE: usize = 256;
+// the upper half of the first RAM page belongs to the stack
+const DATA_LIMIT: usize = 0x80;
+
+// an exported name, its address and the index of the module that defines it
+type Symbol = (String, u16, usize);
+
+struct Module {
+    name: String,
+    object: robj::Object,
+    code_base: usize,
+    data_base: usize,
+}
+
+fn report_error(err: &str, had_error: &mut bool) {
+    println!("Error: {}", err);
+    *had_error = true;
+}
+
+fn read_module(filename: &str) -> Result<robj::Object, String> {
+    let mut data: Vec<u8> = Vec::new();
+    if let Err(e) = File::open(filename).and_then(|mut f| f.read_to_end(&mut data)) {
+        return Err(format!("Couldn't read {}: {}", filename, e));
+    }
+    let object: robj::Object = match robj::decode(&data) {
+        Ok(object) => object,
+        Err(e) => return Err(format!("{}: {}", filename, e)),
+    };
+    if object.isa != rbin::ISA_VERSION {
+        return Err(format!("{} was built for ISA version {} but this linker targets {}", filename, object.isa, rbin::ISA_VERSION));
+    }
+    Ok(object)
+}
+
+// modules are placed one after another in command line order, code from the bottom of ROM and
+// data from the bottom of RAM
+fn link(modules: &[Module], entry: Option<&String>, had_error: &mut bool) -> Option<(rbin::Image, Vec<Symbol>)> {
+    let mut symbols: HashMap<String, (u16, usize)> = HashMap::new();
+    for (m, module) in modules.iter().enumerate() {
+        for (name, section, offset) in &module.object.exports {
+            let base: usize = if *section == robj::SECTION_CODE { module.code_base } else { module.data_base };
+            if let Some((_, first)) = symbols.get(name) {
+                report_error(&format!("{} is defined in both {} and {}", name, modules[*first].name, module.name), had_error);
+                continue;
+            }
+            symbols.insert(name.clone(), ((base + *offset as usize) as u16, m));
+        }
+    }
+
+    let mut rom: Vec<u8> = vec![0; ROM_SIZE];
+    let mut ram: Vec<u8> = Vec::new();
+    for module in modules {
+        rom[module.code_base..module.code_base + module.object.code.len()].copy_from_slice(&module.object.code);
+        ram.extend_from_slice(&module.object.data);
+    }
+
+    for module in modules {
+        for r in &module.object.relocations {
+            let value: usize = match r.target {
+                robj::TARGET_CODE => module.code_base,
+                robj::TARGET_DATA => module.data_base,
+                _ => {
+                    let name: &String = &module.object.imports[r.import as usize];
+                    match symbols.get(name) {
+                        Some((address, _)) => *address as usize,
+                        None => {
+                            report_error(&format!("Undefined symbol {} referenced from {}", name, module.name), had_error);
+                            continue;
+                        },
+                    }
+                },
+            };
+            let (bytes, base): (&mut Vec<u8>, usize) = if r.section == robj::SECTION_CODE { (&mut rom, module.code_base) } else { (&mut ram, module.data_base) };
+            let at: usize = base + r.offset as usize;
+            if bytes[at] as usize + value > 0xff {
+                report_error(&format!("Relocated address at {} offset {:#04x} in {} doesn't fit in a byte", if r.section == robj::SECTION_CODE { "code" } else { "data" }, r.offset, module.name), had_error);
+                continue;
+            }
+            bytes[at] += value as u8;
+        }
+    }
+    // execution starts at the symbol given with --entry, otherwise at the first byte of ROM
+    let entry: u16 = match entry {
+        Some(name) => match symbols.get(name) {
+            Some((address, _)) => *address,
+            None => {
+                report_error(&format!("Entry point {} isn't exported by any module", name), had_error);
+                0
+            },
+        },
+        None => 0,
+    };
+    if *had_error {
+        return None;
+    }
+
+    let mut table: Vec<Symbol> = symbols.into_iter().map(|(name, (address, m))| (name, address, m)).collect();
+    table.sort_by(|x, y| x.1.cmp(&y.1).then(x.0.cmp(&y.0)));
+
+    let mut image: rbin::Image = rbin::new_image(rom, false);
+    image.ram = ram;
+    image.entry = entry;
+    image.symbols = table.iter().map(|s| (s.0.clone(), s.1)).collect();
+    Some((image, table))
+}
+
+fn write_map(filename: &Path, modules: &[Module], table: &[Symbol]) -> std::io::Result<()> {
+    let mut map: String = String::new();
+    map.push_str("module                code  size  data  size\n");
+    for module in modules {
+        map.push_str(&format!("{:<20}  {:04x}  {:04x}  {:04x}  {:04x}\n", module.name, module.code_base, module.object.code.len(), module.data_base, module.object.data.len()));
+    }
+    map.push_str("\nsymbol                address  module\n");
+    for (name, address, m) in table {
+        map.push_str(&format!("{:<20}  {:04x}     {}\n", name, address, modules[*m].name));
+    }
+    File::create(filename)?.write_all(map.as_bytes())
+}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let mut inputs: Vec<&String> = Vec::new();
+    let mut output: Option<&String> = None;
+    let mut map: Option<&String> = None;
+    let mut entry: Option<&String> = None;
+    let mut n = 1;
+    while n < args.len() {
+        if (&args[n][..] == "-o" || &args[n][..] == "--map" || &args[n][..] == "--entry") && n + 1 < args.len() {
+            if &args[n][..] == "-o" {
+                output = Some(&args[n + 1]);
+            } else if &args[n][..] == "--map" {
+                map = Some(&args[n + 1]);
+            } else {
+                entry = Some(&args[n + 1]);
+            }
+            n += 1;
+        } else {
+            inputs.push(&args[n]);
+        }
+        n += 1;
+    }
+    if inputs.is_empty() {
+        println!("Usage: linker <module.robj>... [-o out.rbin] [--map out.map] [--entry symbol]");
+        return Ok(());
+    }
+    let output = match output {
+        Some(f) => Path::new(f).to_path_buf(),
+        None => Path::new(inputs[0]).with_extension("rbin"),
+    };
+    let map = match map {
+        Some(f) => Path::new(f).to_path_buf(),
+        None => output.with_extension("map"),
+    };
+
+    let mut had_error: bool = false;
+    let mut modules: Vec<Module> = Vec::new();
+    let mut code_base: usize = 0;
+    let mut data_base: usize = 0;
+    for filename in &inputs {
+        match read_module(filename) {
+            Ok(object) => {
+                let (code_len, data_len) = (object.code.len(), object.data.len());
+                modules.push(Module { name: filename.to_string(), object, code_base, data_base });
+                code_base += code_len;
+                data_base += data_len;
+            },
+            Err(e) => report_error(&e, &mut had_error),
+        }
+    }
+    if code_base > ROM_SIZE {
+        report_error(&format!("Code is {} bytes but the ROM holds {}", code_base, ROM_SIZE), &mut had_error);
+    }
+    if data_base > DATA_LIMIT {
+        report_error(&format!("Data is {} bytes and runs into the stack at {:#04x}", data_base, DATA_LIMIT), &mut had_error);
+    }
+
+    if !had_error {
+        if let Some((image, table)) = link(&modules, entry, &mut had_error) {
+            File::create(&output)?.write_all(&rbin::encode(&image))?;
+            write_map(&map, &modules, &table)?;
+        }
+    }
+    if had_error {
+        println!("Failed to link.");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a module placed at the given bases, exporting its first code byte under name
+    fn module(name: &str, code: Vec<u8>, imports: &[&str], relocations: Vec<robj::Relocation>, code_base: usize, data_base: usize) -> Module {
+        let object: robj::Object = robj::Object {
+            isa: rbin::ISA_VERSION,
+            code,
+            data: vec![0x00],
+            exports: vec![(name.to_string(), robj::SECTION_CODE, 0), (format!("{}_data", name), robj::SECTION_DATA, 0)],
+            imports: imports.iter().map(|i| i.to_string()).collect(),
+            relocations,
+        };
+        Module { name: format!("{}.robj", name), object, code_base, data_base }
+    }
+
+    fn import(offset: u16, n: u16) -> robj::Relocation {
+        robj::Relocation { section: robj::SECTION_CODE, offset, target: robj::TARGET_IMPORT, import: n }
+    }
+
+    fn local(offset: u16, target: u8) -> robj::Relocation {
+        robj::Relocation { section: robj::SECTION_CODE, offset, target, import: 0 }
+    }
+
+    #[test]
+    fn imports_are_resolved() {
+        // JMP other, then LDR from this module's own code and data
+        let modules: Vec<Module> = vec![
+            module("main", vec![0x50, 0x01, 0x0c, 0x02, 0xff], &["other_data", "other"], vec![import(1, 1), local(3, robj::TARGET_CODE)], 0, 0),
+            module("other", vec![0xff, 0xff, 0x01], &[], vec![local(2, robj::TARGET_DATA)], 5, 1),
+        ];
+        let mut had_error: bool = false;
+        let (image, table) = link(&modules, None, &mut had_error).unwrap();
+        assert!(!had_error);
+        assert_eq!(&image.rom[..8], &[0x50, 0x06, 0x0c, 0x02, 0xff, 0xff, 0xff, 0x02]);
+        assert_eq!(image.ram, vec![0x00, 0x00]);
+        assert_eq!(image.entry, 0);
+        assert_eq!(table[table.len() - 1], ("other".to_string(), 5, 1));
+        assert!(table.contains(&("other_data".to_string(), 1, 1)));
+    }
+
+    #[test]
+    fn link_errors() {
+        let mut had_error: bool = false;
+        let missing: Vec<Module> = vec![module("main", vec![0x50, 0x00], &["nowhere"], vec![import(1, 0)], 0, 0)];
+        assert!(link(&missing, None, &mut had_error).is_none());
+        assert!(had_error);
+
+        // an address plus the base of a module placed high in ROM passes 0xff
+        let mut had_error: bool = false;
+        let overflow: Vec<Module> = vec![module("main", vec![0x50, 0xf0], &[], vec![local(1, robj::TARGET_CODE)], 0x20, 0)];
+        assert!(link(&overflow, None, &mut had_error).is_none());
+        assert!(had_error);
+
+        let mut had_error: bool = false;
+        let twice: Vec<Module> = vec![module("main", vec![0xff], &[], vec![], 0, 0), module("main", vec![0xff], &[], vec![], 1, 1)];
+        assert!(link(&twice, None, &mut had_error).is_none());
+        assert!(had_error);
+    }
+
+    #[test]
+    fn entry_point() {
+        let modules: Vec<Module> = vec![module("main", vec![0xff], &[], vec![], 0, 0), module("start", vec![0xff], &[], vec![], 1, 1)];
+        let mut had_error: bool = false;
+        let (image, _) = link(&modules, Some(&"start".to_string()), &mut had_error).unwrap();
+        assert_eq!(image.entry, 1);
+
+        assert!(link(&modules, Some(&"missing".to_string()), &mut had_error).is_none());
+        assert!(had_error);
+    }
+}
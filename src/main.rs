@@ -3,45 +3,24 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::env;
 
-const A_IN: u16 = 0x1;
-const B_IN: u16 = 0x2;
-const C_IN: u16 = 0x3;
-const IR_IN: u16 = 0x4;
-const MDR_IN: u16 = 0x5;
-const MAR_IN: u16 = 0x6;
-const PC_IN: u16 = 0x7;
-
-const A_OUT: u16 = 0x1 << 3;
-const B_OUT: u16 = 0x2 << 3;
-const C_OUT: u16 = 0x3 << 3;
-const D_OUT: u16 = 0x4 << 3;
-const ROM_OUT: u16 = 0x5 << 3;
-const RAM_OUT: u16 = 0x6 << 3;
-const MDR_OUT: u16 = 0x7 << 3;
-
-const ADD_OUT: u16 = 0x1 << 6;
-const AND_OUT: u16 = 0x2 << 6;
-const NOT_OUT: u16 = 0x3 << 6;
-const OR_OUT: u16 = 0x4 << 6;
-const XOR_OUT: u16 = 0x5 << 6;
-const PC_OUT: u16 = 0x6 << 6;
-const RAM_IN: u16 = 0x7 << 6;
-
-const SP_OUT: u16 = 0x1 << 9;
-const FLAGS_IN: u16 = 0x2 << 9;
-const SUB: u16 = 0x3 << 9;
-const PC_INC: u16 = 0x4 << 9;
-const D_IN: u16 = 0x5 << 9;
-const D_INC: u16 = 0x6 << 9;
-const D_DEC: u16 = 0x7 << 9;
-
-const HLT: u16 = 0x1 << 12;
-const SP_INC: u16 = 0x1 << 13;
-const SP_DEC: u16 = 0x1 << 14;
+mod isa;
+mod rbin;
 
+// control signals and the instruction table are shared with the assembler and disassembler
+use isa::*;
 
+// eeprom address layout: flags | micro step | opcode
+const STEP_BITS: usize = 4; // 16 micro steps per instruction
+const STEPS: usize = 1 << STEP_BITS;
+const FLAGS_SHIFT: usize = 8 + STEP_BITS;
+const EEPROM_SIZE: usize = 1 << (FLAGS_SHIFT + 2); // two flag lines (zero and carry)
+
+const MEMORY_SIZE: usize = 0x10000; // 16 bit address bus
+
+
+#[allow(clippy::upper_case_acronyms)]
 struct CPU {
-    pc: u8, // program counter
+    pc: u16, // program counter
     a: u8, // a(ccumulator) register
     b: u8, // b register (general purpose)
     c: u8, // c register (output)
@@ -51,15 +30,22 @@ struct CPU {
     _or: u8,
     _xor: u8,
     _not: u8,
-    mar: u8, // memory address register
+    mar: u16, // memory address register
+    addr_hi: u8, // latch holding the high byte of a 16 bit address while the low byte is fetched
     mdr: u8, // memory data register
     ir: u8, // instruction register
     bus: u8,
     sp: u8, // stack pointer
-    eeprom: [u16; 8192], // eeprom containing the cpu control signals
-    ram: [u8; 256], // random access memory (upper 128 bytes used by the cpu stack)
-    rom: [u8; 256], // read only memory - contains the program code
+    fp: u8, // frame pointer - base of the current ENTER frame
+    eeprom: [u32; EEPROM_SIZE], // eeprom containing the cpu control signals (two 16 bit wide chips)
+    ram: [u8; MEMORY_SIZE], // random access memory (upper 128 bytes of the first page used by the cpu stack)
+    rom: [u8; MEMORY_SIZE], // read only memory - contains the program code
+    wide: bool, // 16 bit addressing mode - address operands are two bytes (high, low)
+    rom_bank: u8, // 256 byte page of rom visible to the cpu
+    ram_bank: u8, // 256 byte page of ram visible below 0x80 (the stack page above it is shared)
     halt: u8, // program halt signal
+    trap: Option<fn(&mut CPU, u8)>, // host services requested by SYS (wired up by the cpu binary)
+    exit_code: Option<u8>, // status set by the exit service
     flags: u8, // cpu flags - currently only two are used (zero and carry) XXXX XXZC
     had_error: bool,
 }
@@ -90,25 +76,96 @@ fn add (a: u8, mut b: u8, flags: &mut u8, subtract: u8) -> u8 {
 
     for bit in 0..8 {
         let out: u8 = xor(xor((a >> bit) & 0x01, (b >> bit) & 0x01), carry);
-        sum = sum | (out << bit);
+        sum |= out << bit;
         carry = or(and((a >> bit) & 0x01, (b >> bit) & 0x01), and(xor((a >> bit) & 0x01, (b >> bit) & 0x01), carry));
     }
 
     // set carry flag
-    *flags = *flags | (carry << 0);
+    *flags |= carry;
     
     // set zero flag
     if sum == 0x00 {
-        *flags = *flags | (1 << 1);
-    } else {
-        *flags = *flags | (0 << 1);
+        *flags |= 1 << 1;
     }
     sum
 }
 
+fn mul (a: u8, b: u8, flags: &mut u8) -> u16 {
+    let mut lo: u8 = 0x00;
+    let mut hi: u8 = 0x00;
+
+    // shift and add - one partial product for every set bit of b
+    for bit in 0..8 {
+        if (b >> bit) & 0x01 == 0x01 {
+            let shifted: u16 = (a as u16) << bit;
+            let mut carry: u8 = 0x00;
+            lo = add(lo, shifted as u8, &mut carry, 0x00);
+            hi = add(hi, (shifted >> 8) as u8, &mut 0x00, 0x00);
+            hi = add(hi, carry & 0x01, &mut 0x00, 0x00);
+        }
+    }
+
+    // set carry flag when the product doesn't fit in a single byte
+    if hi != 0x00 {
+        *flags |= 1 << 0;
+    }
+
+    // set zero flag
+    if hi == 0x00 && lo == 0x00 {
+        *flags |= 1 << 1;
+    }
+    (hi as u16) << 8 | (lo as u16)
+}
+
+fn div (a: u8, b: u8, flags: &mut u8) -> (u8, u8) {
+    // divide by zero sets the carry flag and leaves the dividend in the remainder
+    if b == 0x00 {
+        *flags |= 1 << 0;
+        return (0xff, a);
+    }
+
+    let mut quotient: u8 = 0x00;
+    let mut remainder: u8 = 0x00;
+
+    // restoring division - the carry out of the subtraction says whether b fits
+    for bit in (0..8).rev() {
+        let overflow: u8 = remainder >> 7;
+        remainder = (remainder << 1) | ((a >> bit) & 0x01);
+        let mut borrow: u8 = 0x00;
+        let difference: u8 = add(remainder, b, &mut borrow, 0x01);
+        if overflow == 0x01 || borrow & 0x01 == 0x01 {
+            remainder = difference;
+            quotient |= 1 << bit;
+        }
+    }
+    (quotient, remainder)
+}
+
+fn rom_address(_cpu: &CPU) -> usize {
+    (((_cpu.rom_bank as usize) << 8) + _cpu.mar as usize) & (MEMORY_SIZE - 1)
+}
+
+fn ram_address(_cpu: &CPU) -> usize {
+    if _cpu.mar < 0x80 {
+        (((_cpu.ram_bank as usize) << 8) + _cpu.mar as usize) & (MEMORY_SIZE - 1)
+    } else {
+        _cpu.mar as usize
+    }
+}
+
+// the PC is 8 bits wide unless 16 bit addressing is on, running off the end of a bank wraps to its
+// start the way the counter chip does
+fn pc_mask(_cpu: &CPU) -> u16 {
+    if _cpu.wide { 0xffff } else { 0x00ff }
+}
+
+fn pc_inc(_cpu: &mut CPU) {
+    _cpu.pc = _cpu.pc.wrapping_add(1) & pc_mask(_cpu);
+}
+
 fn get_address(_cpu: &mut CPU, inst: u8, t: u8) -> u16 {
-    let address: u16 = (t as u16) << 8 | (inst as u16) | (_cpu.flags as u16) << 11;
-    return address;
+    let address: u16 = (t as u16) << 8 | (inst as u16) | (_cpu.flags as u16) << FLAGS_SHIFT;
+    address
 }
 
 
@@ -118,62 +175,146 @@ fn execute_micro_instruction(_cpu: &mut CPU, step: u8) {
         return;
     }
 
-    let micro: u16 = _cpu.eeprom[get_address(_cpu, _cpu.ir, step) as usize];
+    let micro: u32 = _cpu.eeprom[get_address(_cpu, _cpu.ir, step) as usize];
 
     match micro {
-        micro if micro == PC_OUT | MAR_IN /*0x03E*/ => { _cpu.bus = _cpu.pc; _cpu.mar = _cpu.bus; },
-        micro if micro == PC_OUT | MAR_IN | PC_INC /*0x1FE*/ => { _cpu.bus = _cpu.pc; _cpu.mar = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == ROM_OUT | IR_IN | PC_INC /*0x1F4*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.ir = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == ROM_OUT | MAR_IN | PC_INC /*0x1F6*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.mar = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == RAM_OUT | MDR_IN /*0x02D*/ => { _cpu.bus = _cpu.ram[_cpu.mar as usize]; _cpu.mdr = _cpu.bus; },
+        micro if micro == PC_OUT | MAR_IN /*0x03E*/ => { _cpu.bus = _cpu.pc as u8; _cpu.mar = _cpu.pc; },
+        micro if micro == PC_OUT | MAR_IN | PC_INC /*0x1FE*/ => { _cpu.bus = _cpu.pc as u8; _cpu.mar = _cpu.pc; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | IR_IN | PC_INC /*0x1F4*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.ir = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | MAR_IN | PC_INC /*0x1F6*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.mar = _cpu.bus as u16; pc_inc(_cpu); },
+        micro if micro == RAM_OUT | MDR_IN /*0x02D*/ => { _cpu.bus = _cpu.ram[ram_address(_cpu)]; _cpu.mdr = _cpu.bus; },
         micro if micro == MDR_OUT | A_IN /*0x022*/ => { _cpu.bus = _cpu.mdr; _cpu.a = _cpu.bus; },
         micro if micro == MDR_OUT | B_IN /*0x021*/ => { _cpu.bus = _cpu.mdr; _cpu.b = _cpu.bus; },
         micro if micro == MDR_OUT | C_IN /*0x023*/ => { _cpu.bus = _cpu.mdr; _cpu.c = _cpu.bus; },
         micro if micro == MDR_OUT | D_IN /*0x1020*/ => { _cpu.bus = _cpu.mdr; _cpu.d = _cpu.bus; },
-        micro if micro == ADD_OUT | A_IN /*0x00A*/ => { _cpu.alu = add(_cpu.a, _cpu.b, &mut _cpu.flags, 0x00); _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; },
-        micro if micro == ADD_OUT | A_IN | SUB /*0x14A*/ => { _cpu.alu = add(_cpu.a, _cpu.b, &mut _cpu.flags, 0x01); _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; },
+        micro if micro == ADD_OUT | A_IN /*0x00A*/ => { _cpu.flags = 0x00; _cpu.alu = add(_cpu.a, _cpu.b, &mut _cpu.flags, 0x00); _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; },
+        micro if micro == ADD_OUT | A_IN | SUB /*0x14A*/ => { _cpu.flags = 0x00; _cpu.alu = add(_cpu.a, _cpu.b, &mut _cpu.flags, 0x01); _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; },
         micro if micro == AND_OUT | A_IN /*0x182*/  => { _cpu._and = and(_cpu.a, _cpu.b); _cpu.bus = _cpu._and; _cpu.a = _cpu.bus; _cpu.flags = 0x00; if _cpu.a == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; }},
         micro if micro == OR_OUT | A_IN /*0x082*/  => { _cpu._or = or(_cpu.a, _cpu.b); _cpu.bus = _cpu._or; _cpu.a = _cpu.bus; _cpu.flags = 0x00; if _cpu.a == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; }},
         micro if micro == XOR_OUT | A_IN /*0x802*/  => { _cpu._xor = xor(_cpu.a, _cpu.b); _cpu.bus = _cpu._xor; _cpu.a = _cpu.bus; _cpu.flags = 0x00; if _cpu.a == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; }},
         micro if micro == NOT_OUT | A_IN /*0x042*/  => { _cpu._not = not(_cpu.a); _cpu.bus = _cpu._not; _cpu.a = _cpu.bus; _cpu.flags = 0x00; if _cpu.a == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; }},
-        micro if micro == ROM_OUT | PC_IN /*0x037*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.pc = _cpu.bus; },
+        micro if micro == ROM_OUT | PC_IN /*0x037*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.pc = _cpu.bus as u16; },
         micro if micro == A_OUT | C_IN /*0x01B*/ => { _cpu.bus = _cpu.a; _cpu.c = _cpu.bus; println!("{}", _cpu.c); },
-        micro if micro == ROM_OUT | A_IN | PC_INC /*0x1F2*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.a = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == ROM_OUT | B_IN | PC_INC /*0x1F1*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.b = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == ROM_OUT | C_IN | PC_INC /*0x1F3*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.c = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == ROM_OUT | D_IN | PC_INC /*0x11F0*/ => {_cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.d = _cpu.bus; _cpu.pc = _cpu.pc + 1; },
-        micro if micro == A_OUT | RAM_IN /*0x418*/ => { _cpu.bus = _cpu.a; _cpu.ram[_cpu.mar as usize] = _cpu.bus; },
-        micro if micro == B_OUT | RAM_IN /*0x410*/ => { _cpu.bus = _cpu.b; _cpu.ram[_cpu.mar as usize] = _cpu.bus; },
-        micro if micro == C_OUT | RAM_IN /*0x600*/ => { _cpu.bus = _cpu.c; _cpu.ram[_cpu.mar as usize] = _cpu.bus; },
-        micro if micro == D_OUT | RAM_IN /*0x2400*/ => { _cpu.bus = _cpu.d; _cpu.ram[_cpu.mar as usize] = _cpu.bus; },
-        micro if micro == A_OUT | RAM_IN | SP_INC /*0x5D8*/ => { _cpu.bus = _cpu.a; _cpu.ram[(_cpu.mar) as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == B_OUT | RAM_IN | SP_INC /*0x5D0*/ => { _cpu.bus = _cpu.b; _cpu.ram[(_cpu.mar) as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == C_OUT | RAM_IN | SP_INC /*0x7C0*/ => { _cpu.bus = _cpu.c; _cpu.ram[(_cpu.mar) as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == D_OUT | RAM_IN | SP_INC /*0x25C0*/ => { _cpu.bus = _cpu.d; _cpu.ram[(_cpu.mar) as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == ROM_OUT | RAM_IN | SP_INC /*0x530*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.ram[(_cpu.sp)  as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == SP_DEC | RAM_OUT | A_IN /*0x0EA*/ => { _cpu.sp = _cpu.sp - 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.a = _cpu.bus; },
-        micro if micro == SP_DEC | RAM_OUT | B_IN /*0x0E9*/ => { _cpu.sp = _cpu.sp - 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.b = _cpu.bus; },
-        micro if micro == SP_DEC | RAM_OUT | C_IN /*0x0EB*/ => { _cpu.sp = _cpu.sp - 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.c = _cpu.bus; },
-        micro if micro == SP_DEC | RAM_OUT | D_IN /*0x10E8*/ => { _cpu.sp = _cpu.sp - 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.d = _cpu.bus; },
-        micro if micro == PC_OUT | RAM_IN | SP_INC /*0x1B8*/ => { _cpu.bus = _cpu.pc; _cpu.ram[(_cpu.sp) as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        micro if micro == SP_DEC | RAM_OUT | PC_IN /*0x0EF*/ => { _cpu.sp = _cpu.sp - 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.pc = _cpu.bus; },
-        micro if micro == ROM_OUT | RAM_IN /*0x1B0*/ => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.ram[(_cpu.sp)  as usize] = _cpu.bus; },
+        micro if micro == ROM_OUT | A_IN | PC_INC /*0x1F2*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.a = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | B_IN | PC_INC /*0x1F1*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.b = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | C_IN | PC_INC /*0x1F3*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.c = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | D_IN | PC_INC /*0x11F0*/ => {_cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.d = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == A_OUT | RAM_IN /*0x418*/ => { _cpu.bus = _cpu.a; _cpu.ram[ram_address(_cpu)] = _cpu.bus; },
+        micro if micro == B_OUT | RAM_IN /*0x410*/ => { _cpu.bus = _cpu.b; _cpu.ram[ram_address(_cpu)] = _cpu.bus; },
+        micro if micro == C_OUT | RAM_IN /*0x600*/ => { _cpu.bus = _cpu.c; _cpu.ram[ram_address(_cpu)] = _cpu.bus; },
+        micro if micro == D_OUT | RAM_IN /*0x2400*/ => { _cpu.bus = _cpu.d; _cpu.ram[ram_address(_cpu)] = _cpu.bus; },
+        micro if micro == A_OUT | RAM_IN | SP_INC /*0x5D8*/ => { _cpu.bus = _cpu.a; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == B_OUT | RAM_IN | SP_INC /*0x5D0*/ => { _cpu.bus = _cpu.b; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == C_OUT | RAM_IN | SP_INC /*0x7C0*/ => { _cpu.bus = _cpu.c; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == D_OUT | RAM_IN | SP_INC /*0x25C0*/ => { _cpu.bus = _cpu.d; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == ROM_OUT | RAM_IN | SP_INC /*0x530*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.ram[(_cpu.sp)  as usize] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == SP_DEC | RAM_OUT | A_IN /*0x0EA*/ => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.a = _cpu.bus; },
+        micro if micro == SP_DEC | RAM_OUT | B_IN /*0x0E9*/ => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.b = _cpu.bus; },
+        micro if micro == SP_DEC | RAM_OUT | C_IN /*0x0EB*/ => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.c = _cpu.bus; },
+        micro if micro == SP_DEC | RAM_OUT | D_IN /*0x10E8*/ => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.d = _cpu.bus; },
+        micro if micro == FLAGS_OUT | RAM_IN | SP_INC => { _cpu.bus = _cpu.flags; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == SP_DEC | RAM_OUT | FLAGS_IN => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.flags = _cpu.bus & 0x03; },
+        micro if micro == PC_OUT | RAM_IN | SP_INC /*0x1B8*/ => { _cpu.bus = _cpu.pc as u8; _cpu.ram[(_cpu.sp) as usize] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == SP_DEC | RAM_OUT | PC_IN /*0x0EF*/ => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.pc = _cpu.bus as u16; },
+        micro if micro == ROM_OUT | RAM_IN /*0x1B0*/ => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.ram[(_cpu.sp)  as usize] = _cpu.bus; },
         micro if micro == A_OUT | B_IN /*0x019*/ => { _cpu.bus = _cpu.a; _cpu.b = _cpu.bus; },
         micro if micro == C_OUT | B_IN /*0x219*/ => { _cpu.bus = _cpu.c; _cpu.b = _cpu.bus; },
-        micro if micro == PC_INC /*0x1C0*/ => { _cpu.pc = _cpu.pc + 1; },
-        micro if micro == SP_INC /*0x100*/ => { _cpu.sp = _cpu.sp + 1; },
-        micro if micro == D_DEC => { _cpu.d = _cpu.d - 1; _cpu.flags = 0x00; if _cpu.d == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; } },
-        micro if micro == D_INC => { _cpu.d = _cpu.d + 1; },
-        micro if micro == SP_OUT | MAR_IN => { _cpu.bus = _cpu.sp; _cpu.mar = _cpu.bus; },
-        micro if micro == ROM_OUT | MDR_IN => { _cpu.bus = _cpu.rom[_cpu.mar as usize]; _cpu.mdr = _cpu.bus; },
-        micro if micro == MDR_OUT | RAM_IN | SP_INC => { _cpu.bus = _cpu.mdr; _cpu.ram[_cpu.mar as usize] = _cpu.bus; _cpu.sp = _cpu.sp + 1; },
-        _ => return,
+        micro if micro == PC_INC /*0x1C0*/ => { pc_inc(_cpu); },
+        micro if micro == SP_INC /*0x100*/ => { _cpu.sp += 1; },
+        // D wraps like the 8 bit counter it is, LDIR/FILL with D = 0 run 256 times
+        micro if micro == D_DEC => { _cpu.d = _cpu.d.wrapping_sub(1); _cpu.flags = 0x00; if _cpu.d == 0 { _cpu.flags = 0; _cpu.flags = 0x01 << 1; } },
+        micro if micro == D_INC => { _cpu.d = _cpu.d.wrapping_add(1); },
+        micro if micro == TRAP => { _cpu.bus = _cpu.mdr; if let Some(hook) = _cpu.trap { hook(_cpu, _cpu.bus); } },
+        micro if micro == SP_OUT | ADD_OUT | MAR_IN => { _cpu.alu = add(_cpu.sp, _cpu.mdr, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == FP_OUT | ADD_OUT | MAR_IN => { _cpu.alu = add(_cpu.fp, _cpu.mdr, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == SP_OUT | ADD_OUT | SP_IN => { _cpu.alu = add(_cpu.sp, _cpu.mdr, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.sp = _cpu.bus; },
+        micro if micro == FP_OUT | RAM_IN | SP_INC => { _cpu.bus = _cpu.fp; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == SP_DEC | RAM_OUT | FP_IN => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.fp = _cpu.bus; },
+        micro if micro == SP_OUT | FP_IN => { _cpu.bus = _cpu.sp; _cpu.fp = _cpu.bus; },
+        micro if micro == FP_OUT | SP_IN => { _cpu.bus = _cpu.fp; _cpu.sp = _cpu.bus; },
+        micro if micro == SP_OUT | MAR_IN => { _cpu.bus = _cpu.sp; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == ROM_OUT | MDR_IN => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.mdr = _cpu.bus; },
+        micro if micro == MDR_OUT | RAM_IN | SP_INC => { _cpu.bus = _cpu.mdr; _cpu.ram[ram_address(_cpu)] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == MDR_OUT | RAM_IN => { _cpu.bus = _cpu.mdr; _cpu.ram[ram_address(_cpu)] = _cpu.bus; },
+        micro if micro == ADD_OUT | MDR_IN => { _cpu.flags = 0x00; _cpu.alu = add(_cpu.mdr, 0x01, &mut _cpu.flags, 0x00); _cpu.bus = _cpu.alu; _cpu.mdr = _cpu.bus; },
+        micro if micro == ADD_OUT | MAR_IN => { _cpu.alu = add(_cpu.mdr, _cpu.d, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == ADD_OUT | MAR_IN | WIDE => { _cpu.mar = _cpu.mar.wrapping_add(_cpu.d as u16); },
+        micro if micro == ADD_OUT | PC_IN => { _cpu.alu = add(_cpu.pc as u8, _cpu.mdr, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.pc = _cpu.bus as u16; },
+        micro if micro == ADD_OUT | PC_IN | WIDE => { let mut carry: u8 = 0x00; _cpu.alu = add(_cpu.pc as u8, _cpu.mdr, &mut carry, 0x00); let mut hi: u8 = add((_cpu.pc >> 8) as u8, if _cpu.mdr & 0x80 == 0x80 { 0xff } else { 0x00 }, &mut 0x00, 0x00); hi = add(hi, carry & 0x01, &mut 0x00, 0x00); _cpu.bus = _cpu.alu; _cpu.pc = (hi as u16) << 8 | _cpu.bus as u16; },
+        micro if micro == B_OUT | MAR_IN => { _cpu.bus = _cpu.b; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == C_OUT | MAR_IN => { _cpu.bus = _cpu.c; _cpu.mar = _cpu.bus as u16; },
+        micro if micro == ROM_OUT | HI_IN | PC_INC => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.addr_hi = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | MAR_IN | PC_INC | WIDE => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.mar = (_cpu.addr_hi as u16) << 8 | _cpu.bus as u16; pc_inc(_cpu); },
+        micro if micro == ROM_OUT | PC_IN | WIDE => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.pc = (_cpu.addr_hi as u16) << 8 | _cpu.bus as u16; },
+        micro if micro == MDR_OUT | PC_IN | WIDE => { _cpu.bus = _cpu.mdr; _cpu.pc = (_cpu.addr_hi as u16) << 8 | _cpu.bus as u16; },
+        micro if micro == PC_OUT | RAM_IN | SP_INC | WIDE => { _cpu.bus = (_cpu.pc >> 8) as u8; _cpu.ram[(_cpu.sp) as usize] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == SP_DEC | RAM_OUT | HI_IN => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.addr_hi = _cpu.bus; },
+        micro if micro == SP_DEC | RAM_OUT | PC_IN | WIDE => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.pc = (_cpu.addr_hi as u16) << 8 | _cpu.bus as u16; },
+        micro if micro == PC_OUT | RAM_IN | SP_INC | BANK_OUT => { _cpu.bus = _cpu.rom_bank; _cpu.ram[(_cpu.sp) as usize] = _cpu.bus; _cpu.sp += 1; },
+        micro if micro == MDR_OUT | PC_IN | BANK_IN => { _cpu.bus = _cpu.mdr; _cpu.pc = _cpu.bus as u16; _cpu.rom_bank = _cpu.addr_hi; },
+        micro if micro == ROM_OUT | PC_IN | BANK_IN => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.pc = _cpu.bus as u16; _cpu.rom_bank = _cpu.addr_hi; },
+        micro if micro == SP_DEC | RAM_OUT | PC_IN | BANK_IN => { _cpu.sp -= 1; _cpu.bus = _cpu.ram[(_cpu.sp) as usize]; _cpu.pc = _cpu.bus as u16; _cpu.rom_bank = _cpu.addr_hi; },
+        micro if micro == ROM_OUT | RAM_BANK_IN | PC_INC => { _cpu.bus = _cpu.rom[rom_address(_cpu)]; _cpu.ram_bank = _cpu.bus; pc_inc(_cpu); },
+        micro if micro == CD_OUT | MAR_IN => { _cpu.mar = (_cpu.c as u16) << 8 | _cpu.d as u16; },
+        micro if micro == CD_INC => { let cd: u16 = ((_cpu.c as u16) << 8 | _cpu.d as u16).wrapping_add(1); _cpu.c = (cd >> 8) as u8; _cpu.d = cd as u8; },
+        micro if micro == CD_DEC => { let cd: u16 = ((_cpu.c as u16) << 8 | _cpu.d as u16).wrapping_sub(1); _cpu.c = (cd >> 8) as u8; _cpu.d = cd as u8; _cpu.flags = 0x00; if cd == 0 { _cpu.flags = 0x01 << 1; } },
+        micro if micro == B_INC | C_INC => { _cpu.b = _cpu.b.wrapping_add(1); _cpu.c = _cpu.c.wrapping_add(1); },
+        micro if micro == C_INC => { _cpu.c = _cpu.c.wrapping_add(1); },
+        micro if micro == PC_DEC => { _cpu.pc = _cpu.pc.wrapping_sub(1) & pc_mask(_cpu); },
+        micro if micro == MUL_LO_OUT | MDR_IN => { _cpu.flags = 0x00; _cpu.alu = mul(_cpu.a, _cpu.b, &mut _cpu.flags) as u8; _cpu.bus = _cpu.alu; _cpu.mdr = _cpu.bus; },
+        micro if micro == MUL_HI_OUT | A_IN => { _cpu.flags = 0x00; _cpu.alu = (mul(_cpu.a, _cpu.b, &mut _cpu.flags) >> 8) as u8; _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; },
+        micro if micro == MOD_OUT | MDR_IN => { _cpu.flags = 0x00; _cpu.alu = div(_cpu.a, _cpu.b, &mut _cpu.flags).1; _cpu.bus = _cpu.alu; _cpu.mdr = _cpu.bus; },
+        micro if micro == MOD_OUT | A_IN => { _cpu.flags = 0x00; _cpu.alu = div(_cpu.a, _cpu.b, &mut _cpu.flags).1; _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; if _cpu.a == 0 { _cpu.flags |= 0x01 << 1; } },
+        micro if micro == DIV_OUT | A_IN => { _cpu.flags = 0x00; _cpu.alu = div(_cpu.a, _cpu.b, &mut _cpu.flags).0; _cpu.bus = _cpu.alu; _cpu.a = _cpu.bus; if _cpu.a == 0 { _cpu.flags |= 0x01 << 1; } },
+        micro if micro == ADD_OUT | MDR_IN | SUB => { _cpu.flags = 0x00; _cpu.alu = add(_cpu.mdr, 0x01, &mut _cpu.flags, 0x01); _cpu.bus = _cpu.alu; _cpu.mdr = _cpu.bus; },
+        _ => (),
     }
 }
 
+// SYS services: 0 exit with status A, 1 print A as a character, 2 print the zero terminated
+// string in RAM at C:D, 3 read a byte from stdin into A (carry set at end of input), 4 dump registers
+fn host_trap(_cpu: &mut CPU, service: u8) {
+    match service {
+        0x00 => { _cpu.exit_code = Some(_cpu.a); _cpu.halt = 0x00; },
+        0x01 => { print!("{}", _cpu.a as char); },
+        0x02 => {
+            _cpu.mar = (_cpu.c as u16) << 8 | _cpu.d as u16;
+            // a string missing its NUL stops after one pass over memory
+            for _ in 0..MEMORY_SIZE {
+                let byte: u8 = _cpu.ram[ram_address(_cpu)];
+                if byte == 0x00 {
+                    break;
+                }
+                print!("{}", byte as char);
+                _cpu.mar = _cpu.mar.wrapping_add(1);
+            }
+        },
+        0x03 => {
+            let mut byte: [u8; 1] = [0];
+            _cpu.flags = 0x00;
+            match std::io::stdin().read(&mut byte) {
+                Ok(1) => { _cpu.a = byte[0]; },
+                _ => { _cpu.a = 0x00; _cpu.flags = 0x01; },
+            }
+            if _cpu.a == 0x00 {
+                _cpu.flags |= 0x01 << 1;
+            }
+        },
+        0x04 => {
+            println!("pc={:04x} a={:02x} b={:02x} c={:02x} d={:02x} sp={:02x} fp={:02x} flags={:02b}", _cpu.pc, _cpu.a, _cpu.b, _cpu.c, _cpu.d, _cpu.sp, _cpu.fp, _cpu.flags);
+        },
+        _ => {
+            println!("Unknown system call {} at {:04x}", service, _cpu.pc);
+            _cpu.had_error = true;
+            _cpu.halt = 0x00;
+        },
+    }
+    std::io::stdout().flush().unwrap();
+}
+
 fn execute_program(_cpu: &mut CPU) {
     loop {
-        for i in 0..8 {
+        for i in 0..STEPS {
             execute_micro_instruction(_cpu, i as u8);
         }
         if _cpu.halt == 0x00 {
@@ -182,630 +323,30 @@ fn execute_program(_cpu: &mut CPU) {
     }
 }
 
+// the microcode comes from the instruction table - with 16 bit addressing every opcode that takes an
+// absolute address reads two operand bytes (high, low)
 fn load_eeprom(_cpu: &mut CPU) {
     // all opcodes must fetch instruction and increment program counter
-    for i in 0..255 {
+    for i in 0..256 {
         // any flags state
         for j in 0..4 {
-            _cpu.eeprom[(j << 11) | i] = PC_OUT | MAR_IN; // (PC out, MAR in) for all instructions at microstep 0 --> X X 000 XXXX XXXX
-            _cpu.eeprom[(j << 11) | (0x1 << 8) | i] = ROM_OUT | IR_IN | PC_INC; // (rom out, IR in, PC inc) for all instructions at microstep 1 --> X X 001 XXXX XXXX
+            _cpu.eeprom[(j << FLAGS_SHIFT) | i] = PC_OUT | MAR_IN; // (PC out, MAR in) for all instructions at microstep 0 --> X X 0000 XXXX XXXX
+            _cpu.eeprom[(j << FLAGS_SHIFT) | (0x1 << 8) | i] = ROM_OUT | IR_IN | PC_INC; // (rom out, IR in, PC inc) for all instructions at microstep 1 --> X X 0001 XXXX XXXX
         }
     }
 
-    // HALT OPCODE 1111 1111
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xff] = 0x000; // (halt)
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xff] = 0x000; // 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xff] = 0x000; // 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xff] = 0x000; // 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xff] = 0x000; // 
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xff] = 0x000; // 
-    }
-
-    // LOAD A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x00] = PC_OUT | MAR_IN; // (PC out, MAR in)          
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x00] = ROM_OUT | MAR_IN | PC_INC; // (rom out, MAR in, pc inc) 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x00] = RAM_OUT | MDR_IN; //0x02D; // (RAM out, MDR in)         
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x00] = MDR_OUT | A_IN; //0x022; // (MDR out, A in)           
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x00] = 0x000; //                           
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x00] = 0x000; //                           
-    }
-
-    // LOAD B
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x01] = PC_OUT | MAR_IN; // (PC out, MAR in)          
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x01] = ROM_OUT | MAR_IN | PC_INC; // (rom out, MAR in, pc inc) 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x01] = RAM_OUT | MDR_IN; // (RAM out, MDR in)         
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x01] = MDR_OUT | B_IN; // (MDR out, B in)           
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x01] = 0x000; //                           
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x01] = 0x000; //                           
-    }
-
-    // LOAD C
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x02] = PC_OUT | MAR_IN; // (PC out, MAR in)          
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x02] = ROM_OUT | MAR_IN | PC_INC; // (rom out, MAR in, pc inc) 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x02] = RAM_OUT | MDR_IN; // (RAM out, MDR in)         
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x02] = MDR_OUT | C_IN; // (MDR out, C in)           
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x02] = 0x000; //                           
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x02] = 0x000; //                           
-    }
-
-    // LOAD D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x03] = PC_OUT | MAR_IN; // (PC out, MAR in)          
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x03] = ROM_OUT | MAR_IN | PC_INC; // (rom out, MAR in, pc inc) 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x03] = RAM_OUT | MDR_IN; // (RAM out, MDR in)         
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x03] = MDR_OUT | D_IN; // (MDR out, D in)           
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x03] = 0x000; //                           
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x03] = 0x000; //                           
-    }
-
-    // LOAD A IMMEDIATE 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x04] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x04] = ROM_OUT | A_IN | PC_INC; // rom out, A in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x04] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x04] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x04] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x04] = 0x000;
-    }
-
-    // LOAD B IMMEDIATE 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x05] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x05] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x05] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x05] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x05] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x05] = 0x000;
-    }
-
-    // LOAD C IMMEDIATE 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x06] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x06] = ROM_OUT | C_IN | PC_INC; // rom out, C in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x06] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x06] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x06] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x06] = 0x000;
-    }
-
-    // LOAD C IMMEDIATE 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x07] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x07] = ROM_OUT | D_IN | PC_INC; // rom out, D in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x07] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x07] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x07] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x07] = 0x000;
-    }
-
-    // STORE A OPCODE
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x10] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x10] = ROM_OUT | MAR_IN | PC_INC; // rom out, MAR in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x10] = A_OUT | RAM_IN; // A out, RAM in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x10] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x10] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x10] = 0x000;
-    }
-
-    // STORE B OPCODE
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x11] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x11] = ROM_OUT | MAR_IN | PC_INC; // rom out, MAR in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x11] = B_OUT | RAM_IN; // B out, RAM in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x11] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x11] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x11] = 0x000;
-    }
-
-    // STORE C OPCODE
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x12] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x12] = ROM_OUT | MAR_IN | PC_INC; // rom out, MAR in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x12] = C_OUT | RAM_IN; // C out, RAM in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x12] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x12] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x12] = 0x000;
-    }
-
-    // STORE D OPCODE
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x13] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x13] = ROM_OUT | MAR_IN | PC_INC; // rom out, MAR in, PC inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x13] = D_OUT | RAM_IN; // D out, RAM in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x13] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x13] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x13] = 0x000;
-    }
-
-    // PUSH (from register A)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x20] = SP_OUT | MAR_IN; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x20] = A_OUT | RAM_IN | SP_INC;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x20] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x20] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x20] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x20] = 0x000;
-    }
-
-    // PUSH (from register B) 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x21] = SP_OUT | MAR_IN; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x21] = B_OUT | RAM_IN | SP_INC;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x21] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x21] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x21] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x21] = 0x000;
-    }
-
-    // PUSH (from register C) 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x22] = SP_OUT | MAR_IN; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x22] = C_OUT | RAM_IN | SP_INC;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x22] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x22] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x22] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x22] = 0x000;
-    }
-
-    // PUSH (from register D) 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x23] = SP_OUT | MAR_IN; // D out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x23] = D_OUT | RAM_IN | SP_INC;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x23] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x23] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x23] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x23] = 0x000;
-    }
-
-    // PUSH (immediate)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x24] = PC_OUT | MAR_IN;
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x24] = ROM_OUT | MDR_IN;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x24] = SP_OUT | MAR_IN; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x24] = MDR_OUT | RAM_IN | SP_INC;  
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x24] = PC_INC; 
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x24] = 0x000; 
-    }
-
-    // POP (to register A)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x30] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x30] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x30] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x30] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x30] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x30] = 0x000;
-    }
-
-    // POP (to register B)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x31] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x31] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x31] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x31] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x31] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x31] = 0x000;
-    }
-
-    // POP (to register C)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x32] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x32] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x32] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x32] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x32] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x32] = 0x000;
-    }
-
-    // POP (to register D)
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x33] = RAM_OUT | D_IN | SP_DEC; // RAM out, D in, SP dec
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x33] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x33] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x33] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x33] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x33] = 0x000;
-    }
-
-    // SWAP A with B
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x41] = A_OUT | RAM_IN | SP_INC; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x41] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x41] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x41] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x41] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x41] = 0x000;
-    }
-
-    // SWAP A with C
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x42] = A_OUT | RAM_IN | SP_INC; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x42] = C_OUT | RAM_IN | SP_INC; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x42] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x42] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x42] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x42] = 0x000;
-    }
-
-    // SWAP A with D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x43] = A_OUT | RAM_IN | SP_INC; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x43] = D_OUT | RAM_IN | SP_INC; // D out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x43] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x43] = RAM_OUT | D_IN | SP_DEC; // RAM out, D in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x43] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x43] = 0x000;
-    }
-
-    // SWAP B with A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x44] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x44] = A_OUT | RAM_IN | SP_INC; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x44] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x44] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x44] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x44] = 0x000; 
-    }
-
-    // SWAP B with C
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x46] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x46] = C_OUT | RAM_IN | SP_INC; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x46] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x46] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x46] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x46] = 0x000;
-    }
-    
-    // SWAP B with D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x47] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x47] = D_OUT | RAM_IN | SP_INC; // D out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x47] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x47] = RAM_OUT | D_IN | SP_DEC; // RAM out, D in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x47] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x47] = 0x000;
-    }
-
-    // SWAP C with A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x48] = C_OUT | RAM_IN | SP_INC; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x48] = A_OUT | RAM_IN | SP_INC; // A out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x48] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x48] = RAM_OUT | A_IN | SP_DEC; // RAM out, A in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x48] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x48] = 0x000;
-    }
-
-    // SWAP C with B
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x49] = C_OUT | RAM_IN | SP_INC; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x49] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x49] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x49] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x49] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x49] = 0x000;
-    }
-
-    // SWAP C with D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x4b] = C_OUT | RAM_IN | SP_INC; // C out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x4b] = D_OUT | RAM_IN | SP_INC; // D out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x4b] = RAM_OUT | C_IN | SP_DEC; // RAM out, C in, SP dec
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x4b] = RAM_OUT | D_IN | SP_DEC; // RAM out, D in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x4b] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x4b] = 0x000;
-    }
-
-     // JUMP
-     for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x50] = PC_OUT | MAR_IN | PC_INC; // PC out, MAR in, PC inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x50] = ROM_OUT | PC_IN; // rom out, PC in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x50] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x50] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x50] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x50] = 0x000;
-    }
-
-    // JUMP EQUAL ZERO
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x60] = PC_OUT | MAR_IN | PC_INC; // PC out, MAR in, PC inc
-        if i == 2 || i == 3 {
-            _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x60] = ROM_OUT | PC_IN; // rom out, PC in
+    for instruction in isa::INSTRUCTIONS.iter() {
+        for i in 0..4 {
+            let steps: &[u32] = instruction.microcode(i, _cpu.wide);
+            for step in 2..STEPS {
+                _cpu.eeprom[(i << FLAGS_SHIFT) | (step << 8) | instruction.opcode as usize] = if step - 2 < steps.len() { steps[step - 2] } else { 0x000 };
+            }
         }
-        else {
-            _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x60] = 0x000; // do nothing when the zero flag is not set --> 0 X 011 0001 0010
-        }
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x60] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x60] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x60] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x60] = 0x000;
     }
+}
 
-    // JUMP NOT EQUAL ZERO OPCODE 0001 0010
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x70] = PC_OUT | MAR_IN | PC_INC; // PC out, MAR in, PC inc
-        if i == 0 || i == 1 {
-            _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x70] = ROM_OUT | PC_IN; // rom out, PC in        
-        }
-        else {
-            _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x70] = 0x000; // do nothing when the zero flag is set --> 1 X 011 0001 0010
-        }
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x70] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x70] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x70] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x70] = 0x000;
-    }
-
-    // OUT 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xA0] = A_OUT | C_IN; // A out, C in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xA0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xA0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xA0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xA0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xA0] = 0x000;
-    }
-
-    // CALL 
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x80] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x80] = PC_OUT | MAR_IN | PC_INC; // PC out, MAR in, PC inc 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x80] = PC_OUT | RAM_IN | SP_INC; // PC out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x80] = ROM_OUT | PC_IN; // rom out, PC in
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x80] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x80] = 0x000;
-    }
-
-    // RETURN OPCODE 0011 0000
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0x90] = RAM_OUT | PC_IN | SP_DEC; // RAM out, PC in, SP dec
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0x90] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0x90] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0x90] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0x90] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0x90] = 0x000;
-    }
-
-    // ADD A to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xB0] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xB0] = A_OUT | B_IN; // A out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xB0] = ADD_OUT | A_IN; // ALU out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xB0] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xB0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xB0] = 0x000; 
-    }
-
-    // ADD B to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xB1] = ADD_OUT | A_IN; // ALU out, A in 
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xB1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xB1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xB1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xB1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xB1] = 0x000; 
-    }
-
-    // ADD C to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xB2] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xB2] = C_OUT | B_IN; // C out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xB2] = ADD_OUT | A_IN; // ALU out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xB2] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xB2] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xB2] = 0x000; 
-    }
-
-    // ADD imm to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xB8] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xB8] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xB8] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xB8] = ADD_OUT | A_IN; // ALU out, A in
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xB8] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xB8] = 0x000;
-    }
-
-    // SUB A to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xC0] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xC0] = A_OUT | B_IN; // A out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xC0] = ADD_OUT | A_IN | SUB; // ALU out, A in, SUB
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xC0] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xC0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xC0] = 0x000; 
-    }
-
-    // SUB B to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xC1] = ADD_OUT | A_IN | SUB; // ALU out, A in, SUB
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xC1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xC1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xC1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xC1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xC1] = 0x000; 
-    }
-
-    // SUB C to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xC2] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xC2] = C_OUT | B_IN; // C out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xC2] = ADD_OUT | A_IN | SUB; // ALU out, A in, SUB
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xC2] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xC2] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xC2] = 0x000; 
-    }
-
-    // SUB imm to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xC8] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xC8] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xC8] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xC8] = ADD_OUT | A_IN | SUB; // ALU out, A in, SUB
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xC8] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xC8] = 0x000;
-    }
-
-    // AND A to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD0] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD0] = A_OUT | B_IN; // A out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD0] = AND_OUT | A_IN ; // AND out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD0] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD0] = 0x000; 
-    }
-
-    // AND B to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD1] = AND_OUT | A_IN ; // AND out, A in 
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD1] = 0x000; 
-    }
-
-    // AND C to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD2] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD2] = C_OUT | B_IN; // C out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD2] = AND_OUT | A_IN ; // AND out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD2] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD2] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD2] = 0x000; 
-    }
-
-    // AND imm to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD8] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD8] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD8] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD8] = AND_OUT | A_IN ; // AND out, A in
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD8] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD8] = 0x000;
-    }
-
-    // OR A to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD4] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD4] = A_OUT | B_IN; // A out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD4] = OR_OUT | A_IN ; // OR out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD4] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD4] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD4] = 0x000; 
-    }
-
-    // OR B to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD5] = OR_OUT | A_IN ; // OR out, A in 
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD5] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD5] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD5] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD5] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD5] = 0x000; 
-    }
-
-    // OR C to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xD6] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xD6] = C_OUT | B_IN; // C out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xD6] = OR_OUT | A_IN ; // OR out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xD6] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xD6] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xD6] = 0x000; 
-    }
-
-    // OR imm to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xDC] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xDC] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xDC] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xDC] = OR_OUT | A_IN ; // OR out, A in
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xDC] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xDC] = 0x000;
-    }
-
-    // XOR A to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xE0] = B_OUT | RAM_IN | SP_INC; // B out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xE0] = A_OUT | B_IN; // A out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xE0] = XOR_OUT | A_IN ; // XOR out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xE0] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xE0] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xE0] = 0x000; 
-    }
-
-    // XOR B to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xE1] = XOR_OUT | A_IN ; // XOR out, A in 
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xE1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xE1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xE1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xE1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xE1] = 0x000; 
-    }
-
-    // XOR C to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xE2] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xE2] = C_OUT | B_IN; // C out, B in 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xE2] = XOR_OUT | A_IN ; // XOR out, A in
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xE2] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xE2] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xE2] = 0x000; 
-    }
-
-    // XOR imm to A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xE8] = B_OUT | RAM_IN | SP_INC; // B  out, RAM in, SP inc
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xE8] = PC_OUT | MAR_IN; // PC out, MAR in
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xE8] = ROM_OUT | B_IN | PC_INC; // rom out, B in, PC inc
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xE8] = XOR_OUT | A_IN ; // XOR out, A in
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xE8] = RAM_OUT | B_IN | SP_DEC; // RAM out, B in, SP dec
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xE8] = 0x000;
-    }
-
-    // NOT A
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xE4] = NOT_OUT | A_IN ; // NOT out, A in
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xE4] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xE4] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xE4] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xE4] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xE4] = 0x000; 
-    }
-
-    // DEC D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xA1] = D_DEC; 
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xA1] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xA1] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xA1] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xA1] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xA1] = 0x000; 
-    }
-
-    // INC D
-    for i in 0..4 {
-        _cpu.eeprom[(i << 11) | (0x2 << 8) | 0xA2] = D_INC;
-        _cpu.eeprom[(i << 11) | (0x3 << 8) | 0xA2] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x4 << 8) | 0xA2] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x5 << 8) | 0xA2] = 0x000; 
-        _cpu.eeprom[(i << 11) | (0x6 << 8) | 0xA2] = 0x000;
-        _cpu.eeprom[(i << 11) | (0x7 << 8) | 0xA2] = 0x000; 
-    }
-}
-
-
-
-fn create_cpu() -> CPU {
+
+fn create_cpu(wide: bool) -> CPU {
     let mut _cpu = CPU {
         pc: 0,
         a: 0,
@@ -818,48 +359,801 @@ fn create_cpu() -> CPU {
         _xor: 0,
         _not: 0,
         mar: 0,
+        addr_hi: 0,
         mdr: 0,
         ir: 0,
         bus: 0,
         sp: 0x80,
-        eeprom: [0; 8192],
-        ram: [0; 256],
-        rom: [0; 256],
+        fp: 0x80,
+        eeprom: [0; EEPROM_SIZE],
+        ram: [0; MEMORY_SIZE],
+        rom: [0; MEMORY_SIZE],
         halt: 0x01,
+        trap: None,
+        exit_code: None,
+        rom_bank: 0,
+        ram_bank: 0,
+        wide,
         flags: 0,
         had_error: false,
     };
     load_eeprom(&mut _cpu);
-    return _cpu;
+    _cpu
+}
+
+// .rbin is a raw image, .hex is Intel HEX and .mem/.txt are $readmemh or Logisim "v2.0 raw" hex text
+fn rom_format(src: &String) -> Option<&'static str> {
+    match std::path::Path::new(src).extension().and_then(|e| e.to_str()) {
+        Some("rbin") => Some("raw"),
+        Some("hex") => Some("ihex"),
+        Some("mem") | Some("txt") => Some("text"),
+        _ => None,
+    }
+}
+
+fn report_error(_cpu: &mut CPU, err: &str) {
+    println!("Error: {}", err);
+    _cpu.had_error = true;
+}
+
+fn write_rom(_cpu: &mut CPU, address: usize, byte: u8) -> bool {
+    if address >= MEMORY_SIZE {
+        report_error(_cpu, &format!("Address {:#x} is outside the {} byte ROM", address, MEMORY_SIZE));
+        return false;
+    }
+    _cpu.rom[address] = byte;
+    true
+}
+
+fn load_raw(_cpu: &mut CPU, data: &[u8]) {
+    if data.len() > MEMORY_SIZE {
+        report_error(_cpu, &format!("Image is {} bytes but the ROM only holds {}", data.len(), MEMORY_SIZE));
+        return;
+    }
+    // anything past the end of a short image is left zeroed
+    _cpu.rom[..data.len()].copy_from_slice(data);
+}
+
+// without 16 bit addressing the high byte of the entry point is its bank
+fn set_entry(_cpu: &mut CPU, entry: u16) {
+    if _cpu.wide {
+        _cpu.pc = entry;
+    } else {
+        _cpu.pc = entry & 0xff;
+        _cpu.rom_bank = (entry >> 8) as u8;
+    }
+}
+
+fn load_container(_cpu: &mut CPU, data: &[u8]) {
+    let image: rbin::Image = match rbin::decode(data) {
+        Ok(image) => image,
+        Err(e) => { report_error(_cpu, &e); return; },
+    };
+    if image.isa != rbin::ISA_VERSION {
+        report_error(_cpu, &format!("Image was built for ISA version {} but this cpu implements version {}", image.isa, rbin::ISA_VERSION));
+        return;
+    }
+    if image.rom.len() > MEMORY_SIZE || image.ram.len() > MEMORY_SIZE {
+        report_error(_cpu, "Container image is larger than the address space");
+        return;
+    }
+    if _cpu.wide && !image.wide {
+        report_error(_cpu, "Image was assembled for 8 bit addressing but --wide was given");
+        return;
+    }
+    if image.wide && !_cpu.wide {
+        _cpu.wide = true;
+        load_eeprom(_cpu);
+    }
+
+    load_raw(_cpu, &image.rom);
+    for i in 0..image.ram.len() {
+        _cpu.ram[i] = image.ram[i];
+    }
+    set_entry(_cpu, image.entry);
+    _cpu.sp = image.sp;
+    _cpu.fp = image.sp;
+}
+
+fn load_intel_hex(_cpu: &mut CPU, text: &str) {
+    let mut base: usize = 0;
+    let mut ended: bool = false;
+    let mut start: Option<usize> = None;
+    for (n, line) in text.lines().enumerate() {
+        let line: &str = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if ended {
+            report_error(_cpu, &format!("Record after end of file record on line {}", n + 1));
+            return;
+        }
+        if !line.starts_with(':') || line.len() < 11 || line.len().is_multiple_of(2) {
+            report_error(_cpu, &format!("Malformed record on line {}", n + 1));
+            continue;
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut i: usize = 1;
+        while i < line.len() {
+            match u8::from_str_radix(line.get(i..i + 2).unwrap_or("x"), 16) {
+                Ok(y) => bytes.push(y),
+                Err(_) => { report_error(_cpu, &format!("Invalid hex digit on line {}", n + 1)); break; },
+            }
+            i += 2;
+        }
+        if bytes.len() != (line.len() - 1) / 2 {
+            continue;
+        }
+        let length: usize = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            report_error(_cpu, &format!("Record length doesn't match its byte count on line {}", n + 1));
+            continue;
+        }
+        let mut sum: u8 = 0;
+        for b in &bytes {
+            sum = sum.wrapping_add(*b);
+        }
+        if sum != 0 {
+            report_error(_cpu, &format!("Checksum mismatch on line {}", n + 1));
+            continue;
+        }
+        let offset: usize = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        if (bytes[3] == 0x02 || bytes[3] == 0x04) && length != 2 {
+            report_error(_cpu, &format!("Extended address record must hold 2 bytes on line {}", n + 1));
+            continue;
+        }
+        if (bytes[3] == 0x03 || bytes[3] == 0x05) && length != 4 {
+            report_error(_cpu, &format!("Start address record must hold 4 bytes on line {}", n + 1));
+            continue;
+        }
+        match bytes[3] {
+            0x00 => {
+                for i in 0..length {
+                    if !write_rom(_cpu, base + offset + i, bytes[4 + i]) {
+                        return;
+                    }
+                }
+            },
+            0x01 => { ended = true; },
+            0x02 => { base = ((bytes[4] as usize) << 8 | bytes[5] as usize) << 4; },
+            0x04 => { base = ((bytes[4] as usize) << 8 | bytes[5] as usize) << 16; },
+            // start segment address (CS:IP) and start linear address records
+            0x03 => { start = Some(((bytes[4] as usize) << 8 | bytes[5] as usize) * 16 + ((bytes[6] as usize) << 8 | bytes[7] as usize)); },
+            0x05 => { start = Some((bytes[4] as usize) << 24 | (bytes[5] as usize) << 16 | (bytes[6] as usize) << 8 | bytes[7] as usize); },
+            t => { report_error(_cpu, &format!("Unknown record type {:02x} on line {}", t, n + 1)); },
+        }
+    }
+    if !ended {
+        report_error(_cpu, "Missing end of file record");
+    }
+    if let Some(address) = start {
+        if address >= MEMORY_SIZE {
+            report_error(_cpu, &format!("Start address {:#x} is outside the {} byte ROM", address, MEMORY_SIZE));
+        } else {
+            set_entry(_cpu, address as u16);
+        }
+    }
 }
 
-fn validate_filetype(src: &String) -> bool {
-    let v: Vec<&str> = src.split(".").collect();
-    v[1] == "rbin"
+fn load_hex_text(_cpu: &mut CPU, text: &str) {
+    let logisim: bool = text.trim_start().starts_with("v2.0 raw");
+    let mut address: usize = 0;
+    for (n, line) in text.lines().enumerate() {
+        if logisim && n == 0 {
+            continue;
+        }
+        // $readmemh comments are // and Logisim comments are #
+        let line: &str = line.split("//").next().unwrap_or("").split('#').next().unwrap_or("");
+        for word in line.split_whitespace() {
+            if !logisim && word.starts_with('@') {
+                match usize::from_str_radix(&word[1..], 16) {
+                    Ok(y) => address = y,
+                    Err(_) => report_error(_cpu, &format!("Invalid address {} on line {}", word, n + 1)),
+                }
+                continue;
+            }
+            // Logisim run length encoding - count*value
+            let mut count: usize = 1;
+            let mut value: &str = word;
+            if logisim {
+                if let Some((c, v)) = word.split_once('*') {
+                    match c.parse::<usize>() {
+                        Ok(y) => count = y,
+                        Err(_) => { report_error(_cpu, &format!("Invalid repeat count {} on line {}", word, n + 1)); continue; },
+                    }
+                    value = v;
+                }
+            }
+            match u8::from_str_radix(value, 16) {
+                Ok(y) => {
+                    for _ in 0..count {
+                        if !write_rom(_cpu, address, y) {
+                            return;
+                        }
+                        address += 1;
+                    }
+                },
+                Err(_) => { report_error(_cpu, &format!("Invalid byte {} on line {}", word, n + 1)); },
+            }
+        }
+    }
+}
+
+fn load_rom(_cpu: &mut CPU, filename: &String) {
+    let format: &str = match rom_format(filename) {
+        Some(f) => f,
+        None => {
+            report_error(_cpu, "Invalid file type. Only .rbin, .hex, .mem and .txt files can be executed.");
+            return;
+        },
+    };
+
+    let mut data: Vec<u8> = Vec::new();
+    match File::open(filename).and_then(|mut f| f.read_to_end(&mut data)) {
+        Ok(_) => {},
+        Err(e) => {
+            report_error(_cpu, &format!("Couldn't read {}: {}", filename, e));
+            return;
+        },
+    }
+
+    if format == "raw" {
+        if rbin::is_container(&data) {
+            load_container(_cpu, &data);
+        } else {
+            load_raw(_cpu, &data);
+        }
+        return;
+    }
+    match String::from_utf8(data) {
+        Ok(text) => {
+            if format == "ihex" {
+                load_intel_hex(_cpu, &text);
+            } else {
+                load_hex_text(_cpu, &text);
+            }
+        },
+        Err(_) => { report_error(_cpu, "Hex image is not valid text"); },
+    }
+}
+
+fn load_ram(_cpu: &mut CPU, filename: &String) {
+    let mut data: Vec<u8> = Vec::new();
+    match File::open(filename).and_then(|mut f| f.read_to_end(&mut data)) {
+        Ok(_) => {},
+        Err(e) => {
+            report_error(_cpu, &format!("Couldn't read {}: {}", filename, e));
+            return;
+        },
+    }
+    if data.len() > MEMORY_SIZE {
+        report_error(_cpu, &format!("RAM image is {} bytes but the RAM only holds {}", data.len(), MEMORY_SIZE));
+        return;
+    }
+    _cpu.ram[..data.len()].copy_from_slice(&data[..]);
+}
+
+// .txt and .hex dumps are a readable hexdump (repeated lines collapsed to *), anything else is raw bytes
+fn dump_ram(_cpu: &CPU, filename: &String) -> std::io::Result<()> {
+    let mut output = File::create(filename)?;
+    let extension = std::path::Path::new(filename).extension().and_then(|e| e.to_str());
+    if extension != Some("txt") && extension != Some("hex") {
+        return output.write_all(&_cpu.ram);
+    }
+
+    let mut text: String = String::new();
+    let mut skipping: bool = false;
+    let mut i: usize = 0;
+    while i < MEMORY_SIZE {
+        let line: &[u8] = &_cpu.ram[i..i + 16];
+        if i > 0 && i + 16 < MEMORY_SIZE && line == &_cpu.ram[i - 16..i] {
+            if !skipping {
+                text.push_str("*\n");
+                skipping = true;
+            }
+            i += 16;
+            continue;
+        }
+        skipping = false;
+        text.push_str(&format!("{:04x}:", i));
+        for b in line {
+            text.push_str(&format!(" {:02x}", b));
+        }
+        text.push_str("  |");
+        for b in line {
+            text.push(if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' });
+        }
+        text.push_str("|\n");
+        i += 16;
+    }
+    output.write_all(text.as_bytes())
+}
+
+// options that are followed by a file name
+const VALUE_OPTIONS: [&str; 3] = ["--pack", "--ram", "--dump-ram"];
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter().position(|arg| arg == name).and_then(|p| args.get(p + 1))
 }
 
 fn main() ->std::io::Result<()> {
     
     let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    if !validate_filetype(&filename) {
-        println!("Invalid file type. Only .rbin files can be executed.");
-        return Ok(());
+    let wide: bool = args.iter().any(|arg| arg == "--wide");
+    let filename: &String = match args[1..].iter().enumerate().find(|(n, arg)| !arg.starts_with("--") && !VALUE_OPTIONS.contains(&&args[*n][..])).map(|(_, arg)| arg) {
+        Some(f) => f,
+        None => {
+            println!("Usage: cpu [--wide] [--ram in.bin] [--dump-ram out.bin|out.txt] [--pack out.rbin] <image.rbin|image.hex|image.mem>");
+            return Ok(());
+        },
+    };
+    for option in VALUE_OPTIONS.iter() {
+        if args.iter().any(|arg| arg == option) && option_value(&args, option).is_none() {
+            println!("Expected a file name after {}", option);
+            return Ok(());
+        }
     }
 
-    let mut _cpu = create_cpu();
-
-    let mut f = File::open(filename)?;
-    let mut rom = Vec::new();
-    f.read_to_end(&mut rom)?;
+    let mut _cpu = create_cpu(wide);
+    load_rom(&mut _cpu, filename);
+    if let Some(ram) = option_value(&args, "--ram") {
+        load_ram(&mut _cpu, ram);
+    }
 
-   for i in 0..256 {
-        _cpu.rom[i] = rom[i];
+    // --pack out.rbin wraps whatever was loaded in a container instead of running it
+    if let Some(output) = option_value(&args, "--pack") {
+        if _cpu.had_error {
+            return Ok(());
+        }
+        let mut end: usize = MEMORY_SIZE;
+        while end > 256 && _cpu.rom[end - 1] == 0x00 {
+            end -= 1;
+        }
+        let mut ram_end: usize = MEMORY_SIZE;
+        while ram_end > 0 && _cpu.ram[ram_end - 1] == 0x00 {
+            ram_end -= 1;
+        }
+        let mut image: rbin::Image = rbin::new_image(_cpu.rom[..end].to_vec(), _cpu.wide);
+        image.ram = _cpu.ram[..ram_end].to_vec();
+        image.entry = if _cpu.wide { _cpu.pc } else { (_cpu.rom_bank as u16) << 8 | _cpu.pc };
+        image.sp = _cpu.sp;
+        File::create(output)?.write_all(&rbin::encode(&image))?;
+        return Ok(());
     }
 
+    _cpu.trap = Some(host_trap);
     if !_cpu.had_error {
         execute_program(&mut _cpu);
     } 
 
+    if let Some(output) = option_value(&args, "--dump-ram") {
+        dump_ram(&_cpu, output)?;
+    }
+
+    if let Some(code) = _cpu.exit_code {
+        std::process::exit(code as i32);
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intel_hex(text: &str) -> Box<CPU> {
+        let mut _cpu = Box::new(create_cpu(false));
+        load_intel_hex(&mut _cpu, text);
+        _cpu
+    }
+
+    fn hex_text(text: &str) -> Box<CPU> {
+        let mut _cpu = Box::new(create_cpu(false));
+        load_hex_text(&mut _cpu, text);
+        _cpu
+    }
+
+    // boxed, a test holding a few CPUs would otherwise run out of stack
+    fn run(program: &[u8]) -> Box<CPU> {
+        run_mode(program, false)
+    }
+
+    fn run_mode(program: &[u8], wide: bool) -> Box<CPU> {
+        let mut _cpu = Box::new(create_cpu(wide));
+        _cpu.rom[..program.len()].copy_from_slice(program);
+        execute_program(&mut _cpu);
+        _cpu
+    }
+
+    #[test]
+    fn memory_increment_and_decrement() {
+        // STR #ff, $10; INC $10; STR #5, $11; DEC $11; DEC $11; HALT;
+        let _cpu = run(&[0x14, 0xff, 0x10, 0xa6, 0x10, 0x14, 0x05, 0x11, 0xa5, 0x11, 0xa5, 0x11, 0xff]);
+        assert_eq!(_cpu.ram[0x10], 0x00);
+        assert_eq!(_cpu.ram[0x11], 0x03);
+    }
+
+    #[test]
+    fn rom_table_reads() {
+        // LDR A, 8; MOV D, #2; LDR B, 8, D; HALT; with the table 11 22 33 at 8
+        let _cpu = run(&[0x08, 0x08, 0x07, 0x02, 0x0d, 0x08, 0xff, 0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(_cpu.a, 0x11);
+        assert_eq!(_cpu.b, 0x33);
+    }
+
+    #[test]
+    fn flags_select_the_microcode() {
+        let mut _cpu = Box::new(create_cpu(false));
+        for flags in 0..4 {
+            _cpu.flags = flags;
+            assert_eq!(_cpu.eeprom[get_address(&mut _cpu, 0x00, 1) as usize], ROM_OUT | IR_IN | PC_INC);
+        }
+
+        // MOV A, #1; SUB #1; JEZ 9; MOV B, #ff; HALT; MOV B, #7; HALT;
+        let _cpu = run(&[0x04, 0x01, 0xc8, 0x01, 0x60, 0x09, 0x05, 0xff, 0xff, 0x05, 0x07, 0xff]);
+        assert_eq!(_cpu.b, 0x07);
+    }
+
+    #[test]
+    fn multiply_and_divide() {
+        // MOV A, #ff; MOV B, #3; MUL; HALT;
+        let _cpu = run(&[0x04, 0xff, 0x05, 0x03, 0xf1, 0xff]);
+        assert_eq!((_cpu.a, _cpu.b), (0x02, 0xfd));
+        assert_eq!(_cpu.flags & 0x01, 0x01); // product doesn't fit in a byte
+
+        // MOV A, #17; MOV B, #5; DIV; HALT;
+        let _cpu = run(&[0x04, 0x17, 0x05, 0x05, 0xf2, 0xff]);
+        assert_eq!((_cpu.a, _cpu.b), (0x04, 0x03));
+
+        // MOV A, #17; MOV B, #5; MOD; HALT;
+        let _cpu = run(&[0x04, 0x17, 0x05, 0x05, 0xf3, 0xff]);
+        assert_eq!(_cpu.a, 0x03);
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        // MOV A, #17; MOV B, #0; DIV; HALT;
+        let _cpu = run(&[0x04, 0x17, 0x05, 0x00, 0xf2, 0xff]);
+        assert_eq!((_cpu.a, _cpu.b), (0xff, 0x17));
+        assert_eq!(_cpu.flags & 0x01, 0x01);
+
+        // the remainder is the dividend - MOV A, #17; MOV B, #0; MOD; HALT;
+        let _cpu = run(&[0x04, 0x17, 0x05, 0x00, 0xf3, 0xff]);
+        assert_eq!(_cpu.a, 0x17);
+        assert_eq!(_cpu.flags & 0x01, 0x01);
+    }
+
+    #[test]
+    fn block_copy_and_fill() {
+        // STR #1, $10; STR #2, $11; MOV B, #10; MOV C, #20; MOV D, #2; LDIR; HALT;
+        let _cpu = run(&[0x14, 0x01, 0x10, 0x14, 0x02, 0x11, 0x05, 0x10, 0x06, 0x20, 0x07, 0x02, 0xf4, 0xff]);
+        assert_eq!(&_cpu.ram[0x20..0x23], &[0x01, 0x02, 0x00]);
+        assert_eq!((_cpu.b, _cpu.c, _cpu.d), (0x12, 0x22, 0x00));
+
+        // MOV A, #aa; MOV C, #0; MOV D, #3; FILL; HALT;
+        let _cpu = run(&[0x04, 0xaa, 0x06, 0x00, 0x07, 0x03, 0xf5, 0xff]);
+        assert_eq!(&_cpu.ram[0x00..0x04], &[0xaa, 0xaa, 0xaa, 0x00]);
+    }
+
+    #[test]
+    fn block_count_of_zero_runs_256_times() {
+        // MOV A, #aa; MOV C, #0; MOV D, #0; FILL; HALT;
+        let _cpu = run(&[0x04, 0xaa, 0x06, 0x00, 0x07, 0x00, 0xf5, 0xff]);
+        assert!(_cpu.ram[..0x100].iter().all(|&byte| byte == 0xaa));
+        assert_eq!((_cpu.c, _cpu.d), (0x00, 0x00));
+
+        // MOV B, #40; MOV C, #40; MOV D, #0; LDIR; HALT; - B and C come all the way round
+        let _cpu = run(&[0x05, 0x40, 0x06, 0x40, 0x07, 0x00, 0xf4, 0xff]);
+        assert_eq!((_cpu.b, _cpu.c, _cpu.d), (0x40, 0x40, 0x00));
+    }
+
+    #[test]
+    fn wide_pointer_access() {
+        // MOV CD, #0120; MOV A, #5a; STR A, [CD]; MOV B, [CD]; INC CD; STR B, [CD]; STR A, $0200; HALT;
+        let _cpu = run_mode(&[0x38, 0x01, 0x20, 0x04, 0x5a, 0x3e, 0x3d, 0x39, 0x3f, 0x10, 0x02, 0x00, 0xff], true);
+        assert_eq!(_cpu.b, 0x5a);
+        assert_eq!(&_cpu.ram[0x120..0x122], &[0x5a, 0x5a]);
+        assert_eq!(_cpu.ram[0x200], 0x5a);
+        assert_eq!((_cpu.c, _cpu.d), (0x01, 0x21));
+    }
+
+    #[test]
+    fn pc_wraps() {
+        // JMP $ff; with MOV A, #imm at $ff - the immediate is read from $00 and the HALT from $01
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program[..2].copy_from_slice(&[0x50, 0xff]);
+        program[0xff] = 0x04;
+        let _cpu = run(&program);
+        assert_eq!(_cpu.a, 0x50);
+        assert_eq!(_cpu.pc, 0x02);
+
+        // the same at the end of the 16 bit address space
+        let mut program: Vec<u8> = vec![0; MEMORY_SIZE];
+        program[..3].copy_from_slice(&[0x50, 0xff, 0xff]);
+        program[0xffff] = 0x04;
+        let _cpu = run_mode(&program, true);
+        assert_eq!(_cpu.a, 0x50);
+        assert_eq!(_cpu.pc, 0x02);
+    }
+
+    #[test]
+    fn far_call_and_return() {
+        // FCALL $0110; MOV B, #7; HALT; with MOV A, #2a; FRET; at $0110 in bank 1
+        let mut program: Vec<u8> = vec![0; 0x200];
+        program[..6].copy_from_slice(&[0x81, 0x01, 0x10, 0x05, 0x07, 0xff]);
+        program[0x110..0x113].copy_from_slice(&[0x04, 0x2a, 0x91]);
+        let _cpu = run(&program);
+        assert_eq!((_cpu.a, _cpu.b), (0x2a, 0x07));
+        assert_eq!((_cpu.rom_bank, _cpu.pc), (0x00, 0x06));
+        assert_eq!(_cpu.sp, 0x80);
+    }
+
+    #[test]
+    fn ram_banks() {
+        // RBANK #2; STR #5, $10; STR #6, $90; HALT; - the stack half of RAM isn't banked
+        let _cpu = run(&[0xa8, 0x02, 0x14, 0x05, 0x10, 0x14, 0x06, 0x90, 0xff]);
+        assert_eq!(_cpu.ram[0x210], 0x05);
+        assert_eq!(_cpu.ram[0x90], 0x06);
+        assert_eq!(_cpu.ram[0x10], 0x00);
+    }
+
+    #[test]
+    fn relative_branches() {
+        // MOV A, #1; SUB #1; JRZ +2; MOV B, #ff; JRNZ +2; MOV C, #33; HALT;
+        let _cpu = run(&[0x04, 0x01, 0xc8, 0x01, 0x62, 0x02, 0x05, 0xff, 0x72, 0x02, 0x06, 0x33, 0xff]);
+        assert_eq!((_cpu.b, _cpu.c), (0x00, 0x33));
+
+        // MOV D, #3; DEC; JRNZ -3; JR +2; MOV A, #1; HALT;
+        let _cpu = run(&[0x07, 0x03, 0xa1, 0x72, 0xfd, 0x52, 0x02, 0x04, 0x01, 0xff]);
+        assert_eq!((_cpu.a, _cpu.d), (0x00, 0x00));
+
+        // CALLR +3; HALT; with MOV A, #2a; RET; three bytes on
+        let _cpu = run(&[0x82, 0x03, 0xff, 0x00, 0x00, 0x04, 0x2a, 0x90]);
+        assert_eq!((_cpu.a, _cpu.pc, _cpu.sp), (0x2a, 0x03, 0x80));
+    }
+
+    #[test]
+    fn wide_relative_jump_carries() {
+        // JMP $00fe; with JR +4 at $00fe, landing on HALT at $0104
+        let mut program: Vec<u8> = vec![0; 0x200];
+        program[..3].copy_from_slice(&[0x50, 0x00, 0xfe]);
+        program[0xfe..0x100].copy_from_slice(&[0x52, 0x04]);
+        program[0x100..0x102].copy_from_slice(&[0x04, 0x01]);
+        program[0x104] = 0xff;
+        let _cpu = run_mode(&program, true);
+        assert_eq!((_cpu.a, _cpu.pc), (0x00, 0x105));
+    }
+
+    #[test]
+    fn carry_branches() {
+        // MOV A, #ff; ADD #1; JC 8; MOV B, #ff; JNC 12; MOV C, #33; HALT;
+        let _cpu = run(&[0x04, 0xff, 0xb8, 0x01, 0x63, 0x08, 0x05, 0xff, 0x73, 0x0c, 0x06, 0x33, 0xff]);
+        assert_eq!((_cpu.b, _cpu.c), (0x00, 0x33));
+
+        // MOV A, #1; ADD #1; JC 8; JNC 10; MOV B, #ff; HALT;
+        let _cpu = run(&[0x04, 0x01, 0xb8, 0x01, 0x63, 0x08, 0x73, 0x0a, 0x05, 0xff, 0xff]);
+        assert_eq!(_cpu.b, 0x00);
+    }
+
+    #[test]
+    fn conditional_calls_and_returns() {
+        // MOV A, #1; SUB #1; CALLZ 10; CALLNZ 13; HALT;
+        // 10: RETZ; MOV B, #ff; 13: MOV C, #ff; RET;
+        let _cpu = run(&[0x04, 0x01, 0xc8, 0x01, 0x83, 0x0a, 0x84, 0x0d, 0xff, 0x00, 0x93, 0x05, 0xff, 0x06, 0xff, 0x90]);
+        assert_eq!((_cpu.b, _cpu.c), (0x00, 0x00));
+        assert_eq!((_cpu.pc, _cpu.sp), (0x09, 0x80));
+
+        // MOV A, #2; SUB #1; CALLZ 16; CALLNZ 10; HALT;
+        // 10: RETZ; MOV C, #33; RETNZ; 16: MOV B, #ff; RET;
+        let _cpu = run(&[0x04, 0x02, 0xc8, 0x01, 0x83, 0x10, 0x84, 0x0a, 0xff, 0x00, 0x93, 0x06, 0x33, 0x94, 0x00, 0x00, 0x05, 0xff, 0x90]);
+        assert_eq!((_cpu.b, _cpu.c), (0x00, 0x33));
+        assert_eq!((_cpu.pc, _cpu.sp), (0x09, 0x80));
+    }
+
+    #[test]
+    fn context_save_round_trip() {
+        // MOV A, #ff; ADD #1; MOV B, #2; MOV C, #3; MOV D, #4; PUSHALL;
+        // MOV A, #9; MOV B, #9; MOV C, #9; MOV D, #9; ADD #1; POPALL; HALT;
+        let _cpu = run(&[
+            0x04, 0xff, 0xb8, 0x01, 0x05, 0x02, 0x06, 0x03, 0x07, 0x04, 0x26,
+            0x04, 0x09, 0x05, 0x09, 0x06, 0x09, 0x07, 0x09, 0xb8, 0x01, 0x36, 0xff,
+        ]);
+        assert_eq!((_cpu.a, _cpu.b, _cpu.c, _cpu.d), (0x00, 0x02, 0x03, 0x04));
+        assert_eq!(_cpu.flags, 0x03);
+        assert_eq!(_cpu.sp, 0x80);
+        assert_eq!(&_cpu.ram[0x80..0x85], &[0x00, 0x02, 0x03, 0x04, 0x03]);
+
+        // MOV A, #ff; ADD #1; PUSHF; ADD #1; POPF; HALT;
+        let _cpu = run(&[0x04, 0xff, 0xb8, 0x01, 0x25, 0xb8, 0x01, 0x35, 0xff]);
+        assert_eq!((_cpu.a, _cpu.flags, _cpu.sp), (0x01, 0x03, 0x80));
+    }
+
+    #[test]
+    fn stack_frames() {
+        // PUSH #7; CALL 5; HALT; - CALL takes two stack bytes and ENTER one, so the argument is at FP-4
+        // 5: ENTER #2; MOV A, [FP-4]; ADD #1; STR A, [FP+1]; MOV C, [SP-1]; LEAVE; RET;
+        let _cpu = run(&[0x24, 0x07, 0x80, 0x05, 0xff, 0x27, 0x02, 0x28, 0xfc, 0xb8, 0x01, 0x2c, 0x01, 0x1a, 0xff, 0x37, 0x90]);
+        assert_eq!((_cpu.a, _cpu.c), (0x08, 0x08));
+        assert_eq!(_cpu.ram[0x85], 0x08);
+        assert_eq!((_cpu.sp, _cpu.fp), (0x81, 0x80));
+    }
+
+    #[test]
+    fn system_calls() {
+        let mut _cpu = Box::new(create_cpu(false));
+        _cpu.trap = Some(host_trap);
+        // MOV A, #2a; SYS #0; MOV B, #1; HALT;
+        _cpu.rom[..7].copy_from_slice(&[0x04, 0x2a, 0xaa, 0x00, 0x05, 0x01, 0xff]);
+        execute_program(&mut _cpu);
+        assert_eq!(_cpu.exit_code, Some(0x2a));
+        assert_eq!(_cpu.b, 0x00);
+
+        // a string without a NUL anywhere in RAM still comes back
+        let mut _cpu = Box::new(create_cpu(false));
+        _cpu.trap = Some(host_trap);
+        _cpu.ram.iter_mut().for_each(|byte| *byte = b'x');
+        // MOV CD, #0000; SYS #2; HALT;
+        _cpu.rom[..6].copy_from_slice(&[0x38, 0x00, 0x00, 0xaa, 0x02, 0xff]);
+        execute_program(&mut _cpu);
+        assert!(!_cpu.had_error);
+        assert_eq!(_cpu.pc, 0x06);
+    }
+
+    #[test]
+    fn raw_images() {
+        let mut _cpu = Box::new(create_cpu(false));
+        load_raw(&mut _cpu, &[0x04, 0x01, 0xff]);
+        assert!(!_cpu.had_error);
+        assert_eq!(&_cpu.rom[..4], &[0x04, 0x01, 0xff, 0x00]);
+
+        let mut _cpu = Box::new(create_cpu(false));
+        load_raw(&mut _cpu, &vec![0; MEMORY_SIZE + 1]);
+        assert!(_cpu.had_error);
+
+        assert_eq!(rom_format(&"./build/x.rbin".to_string()), Some("raw"));
+        assert_eq!(rom_format(&"x.mem".to_string()), Some("text"));
+        assert_eq!(rom_format(&"noextension".to_string()), None);
+    }
+
+    #[test]
+    fn intel_hex_records() {
+        let _cpu = intel_hex(":03001000010203E7\n:020000020010EC\n:01000000AA55\n:00000001FF\n");
+        assert!(!_cpu.had_error);
+        assert_eq!(&_cpu.rom[0x10..0x13], &[0x01, 0x02, 0x03]);
+        // the extended segment address moves the next record up to 0x0010 << 4
+        assert_eq!(_cpu.rom[0x100], 0xaa);
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        assert!(intel_hex(":03001000010203E7\n").had_error); // no end of file record
+        assert!(intel_hex(":03001000010203E8\n:00000001FF\n").had_error); // checksum
+        assert!(intel_hex("03001000010203E7\n:00000001FF\n").had_error); // no colon
+        assert!(intel_hex(":0300100001020G03E7\n:00000001FF\n").had_error);
+        assert!(intel_hex(":04001000010203E6\n:00000001FF\n").had_error); // byte count
+        assert!(intel_hex(":00000006FA\n:00000001FF\n").had_error);
+        assert!(intel_hex(":00000001FF\n:01000000AA55\n").had_error);
+        // extended address records without their two bytes
+        assert!(intel_hex(":00000002FE\n:00000001FF\n").had_error);
+        assert!(intel_hex(":0100000201FC\n:00000001FF\n").had_error);
+        // the extended linear address 0x10000 is past the end of the ROM
+        assert!(intel_hex(":020000040001F9\n:01000000AA55\n:00000001FF\n").had_error);
+    }
+
+    #[test]
+    fn hex_text_formats() {
+        let _cpu = hex_text("// $readmemh\n01 02\n@10 ff // comment\n");
+        assert!(!_cpu.had_error);
+        assert_eq!(&_cpu.rom[0..2], &[0x01, 0x02]);
+        assert_eq!(_cpu.rom[0x10], 0xff);
+
+        let _cpu = hex_text("v2.0 raw\n3*7 # comment\n9\n");
+        assert!(!_cpu.had_error);
+        assert_eq!(&_cpu.rom[0..4], &[0x07, 0x07, 0x07, 0x09]);
+    }
+
+    #[test]
+    fn hex_text_errors() {
+        assert!(hex_text("01 zz\n").had_error);
+        assert!(hex_text("@1x 01\n").had_error);
+        assert!(hex_text("v2.0 raw\nq*7\n").had_error);
+        assert!(hex_text("@ffff 01 02\n").had_error);
+        // @ is only an address in $readmemh files
+        assert!(hex_text("v2.0 raw\n@10\n").had_error);
+    }
+
+    #[test]
+    fn intel_hex_start_address() {
+        // a linear start address of $0123 is offset $23 in bank 1
+        let _cpu = intel_hex(":0400000500000123D3\n:00000001FF\n");
+        assert!(!_cpu.had_error);
+        assert_eq!((_cpu.rom_bank, _cpu.pc), (0x01, 0x23));
+
+        // CS:IP 0010:0004 is $0104
+        let _cpu = intel_hex(":0400000300100004E5\n:00000001FF\n");
+        assert!(!_cpu.had_error);
+        assert_eq!((_cpu.rom_bank, _cpu.pc), (0x01, 0x04));
+
+        assert!(intel_hex(":020000050001F8\n:00000001FF\n").had_error);
+        assert!(intel_hex(":0400000500010000F6\n:00000001FF\n").had_error);
+    }
+
+    #[test]
+    fn container_entry_point() {
+        let mut image: rbin::Image = rbin::new_image(vec![0; 0x200], false);
+        image.rom[0x123..0x126].copy_from_slice(&[0x04, 0x2a, 0xff]);
+        image.entry = 0x0123;
+        image.ram = vec![0x11, 0x22];
+        let mut _cpu = Box::new(create_cpu(false));
+        load_container(&mut _cpu, &rbin::encode(&image));
+        assert!(!_cpu.had_error);
+        assert_eq!((_cpu.rom_bank, _cpu.pc), (0x01, 0x23));
+        assert_eq!(&_cpu.ram[..2], &[0x11, 0x22]);
+        execute_program(&mut _cpu);
+        assert_eq!(_cpu.a, 0x2a);
+
+        // a wide image switches the cpu to 16 bit addressing
+        image.wide = true;
+        let mut _cpu = Box::new(create_cpu(false));
+        load_container(&mut _cpu, &rbin::encode(&image));
+        assert!(_cpu.wide);
+        assert_eq!((_cpu.rom_bank, _cpu.pc), (0x00, 0x123));
+
+        image.isa = rbin::ISA_VERSION + 1;
+        let mut _cpu = Box::new(create_cpu(false));
+        load_container(&mut _cpu, &rbin::encode(&image));
+        assert!(_cpu.had_error);
+    }
+
+    #[test]
+    fn ram_preload_and_dump() {
+        let dir = std::env::temp_dir();
+        let image = dir.join(format!("cpu-ram-{}.bin", std::process::id())).to_string_lossy().into_owned();
+        let dump = dir.join(format!("cpu-ram-{}.txt", std::process::id())).to_string_lossy().into_owned();
+        std::fs::write(&image, [0x2a, 0x00, b'o', b'k']).unwrap();
+        let mut _cpu = Box::new(create_cpu(false));
+        load_ram(&mut _cpu, &image);
+        assert!(!_cpu.had_error);
+        assert_eq!(&_cpu.ram[..5], &[0x2a, 0x00, b'o', b'k', 0x00]);
+
+        // the zero lines after the first collapse to a single *
+        dump_ram(&_cpu, &dump).unwrap();
+        let text = std::fs::read_to_string(&dump).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "0000: 2a 00 6f 6b 00 00 00 00 00 00 00 00 00 00 00 00  |*.ok............|");
+        assert_eq!(lines[1], "0010: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|");
+        assert_eq!(lines[2], "*");
+        assert_eq!(lines.len(), 4);
+
+        dump_ram(&_cpu, &image).unwrap();
+        assert_eq!(std::fs::read(&image).unwrap(), _cpu.ram.to_vec());
+
+        // a RAM image larger than the RAM is refused
+        std::fs::write(&image, vec![0; MEMORY_SIZE + 1]).unwrap();
+        let mut _cpu = Box::new(create_cpu(false));
+        load_ram(&mut _cpu, &image);
+        assert!(_cpu.had_error);
+        std::fs::remove_file(&image).unwrap();
+        std::fs::remove_file(&dump).unwrap();
+    }
+
+    // the fetch runs for every opcode, the rest of each instruction comes from its table entry
+    #[test]
+    fn eeprom_matches_instruction_table() {
+        for wide in [false, true].iter() {
+            let _cpu = Box::new(create_cpu(*wide));
+            for instruction in isa::INSTRUCTIONS.iter() {
+                for flags in 0..4 {
+                    let at = |step: usize| -> u32 { _cpu.eeprom[(flags << FLAGS_SHIFT) | (step << 8) | instruction.opcode as usize] };
+                    assert_eq!(at(0), PC_OUT | MAR_IN);
+                    assert_eq!(at(1), ROM_OUT | IR_IN | PC_INC);
+                    let steps: &[u32] = instruction.microcode(flags, *wide);
+                    assert!(steps.len() <= STEPS - 2, "{} has too many micro steps", instruction.syntax());
+                    for step in 2..STEPS {
+                        assert_eq!(at(step), steps.get(step - 2).copied().unwrap_or(0), "{} flags {} step {}", instruction.syntax(), flags, step);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// .rbin container - shared by the cpu and the assembler
+//
+// offset  size  field
+//      0     4  magic "RBIN"
+//      4     1  container format version
+//      5     1  ISA (microcode) version the image was built for
+//      6     1  flags - bit 0 set for 16 bit addressing
+//      7     1  initial stack pointer
+//      8     2  entry point (high, low)
+//     10     4  ROM image length (big endian, like every other field)
+//     14     4  initial RAM image length (0 when there is none)
+//     18     4  symbol table length in bytes
+//     22        ROM image, RAM image, symbol table
+//
+// symbol table entries are the name, a zero byte, then the address (high, low).
+// Anything without the magic number is treated as a bare ROM dump.
+
+#![allow(dead_code)]
+
+pub const MAGIC: &[u8; 4] = b"RBIN";
+pub const FORMAT_VERSION: u8 = 1;
+pub const ISA_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 22;
+
+pub const FLAG_WIDE: u8 = 0x01;
+
+pub struct Image {
+    pub version: u8,
+    pub isa: u8,
+    pub wide: bool,
+    pub sp: u8,
+    pub entry: u16,
+    pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub symbols: Vec<(String, u16)>,
+}
+
+pub fn new_image(rom: Vec<u8>, wide: bool) -> Image {
+    Image {
+        version: FORMAT_VERSION,
+        isa: ISA_VERSION,
+        wide,
+        sp: 0x80,
+        entry: 0,
+        rom,
+        ram: Vec::new(),
+        symbols: Vec::new(),
+    }
+}
+
+pub fn is_container(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..4] == MAGIC
+}
+
+// big endian, like every field of the .rbin and .robj headers
+pub fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+pub fn read_u32(data: &[u8], at: usize) -> usize {
+    (data[at] as usize) << 24 | (data[at + 1] as usize) << 16 | (data[at + 2] as usize) << 8 | data[at + 3] as usize
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut symbols: Vec<u8> = Vec::new();
+    for (name, address) in &image.symbols {
+        symbols.extend_from_slice(name.as_bytes());
+        symbols.push(0x00);
+        symbols.push((address >> 8) as u8);
+        symbols.push(*address as u8);
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(image.version);
+    out.push(image.isa);
+    out.push(if image.wide { FLAG_WIDE } else { 0x00 });
+    out.push(image.sp);
+    out.push((image.entry >> 8) as u8);
+    out.push(image.entry as u8);
+    write_u32(&mut out, image.rom.len());
+    write_u32(&mut out, image.ram.len());
+    write_u32(&mut out, symbols.len());
+    out.extend_from_slice(&image.rom);
+    out.extend_from_slice(&image.ram);
+    out.extend_from_slice(&symbols);
+    out
+}
+
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if !is_container(data) {
+        return Err("Missing RBIN magic number".to_string());
+    }
+    if data.len() < HEADER_SIZE {
+        return Err("Truncated container header".to_string());
+    }
+    if data[4] != FORMAT_VERSION {
+        return Err(format!("Unsupported container version {} (expected {})", data[4], FORMAT_VERSION));
+    }
+
+    let rom_len: usize = read_u32(data, 10);
+    let ram_len: usize = read_u32(data, 14);
+    let sym_len: usize = read_u32(data, 18);
+    if data.len() != HEADER_SIZE + rom_len + ram_len + sym_len {
+        return Err(format!("Container sections add up to {} bytes but the file is {}", HEADER_SIZE + rom_len + ram_len + sym_len, data.len()));
+    }
+
+    let mut image: Image = new_image(data[HEADER_SIZE..HEADER_SIZE + rom_len].to_vec(), data[6] & FLAG_WIDE == FLAG_WIDE);
+    image.version = data[4];
+    image.isa = data[5];
+    image.sp = data[7];
+    image.entry = (data[8] as u16) << 8 | data[9] as u16;
+    image.ram = data[HEADER_SIZE + rom_len..HEADER_SIZE + rom_len + ram_len].to_vec();
+
+    let table: &[u8] = &data[HEADER_SIZE + rom_len + ram_len..];
+    let mut i: usize = 0;
+    while i < table.len() {
+        let end: usize = match table[i..].iter().position(|b| *b == 0x00) {
+            Some(p) => i + p,
+            None => return Err("Unterminated symbol name".to_string()),
+        };
+        if end + 2 >= table.len() {
+            return Err("Truncated symbol table".to_string());
+        }
+        let name: String = String::from_utf8_lossy(&table[i..end]).to_string();
+        let address: u16 = (table[end + 1] as u16) << 8 | table[end + 2] as u16;
+        image.symbols.push((name, address));
+        i = end + 3;
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Image {
+        let mut image: Image = new_image(vec![0x01, 0x02, 0x00, 0xff], true);
+        image.sp = 0x70;
+        image.entry = 0x1234;
+        image.ram = vec![0xaa, 0xbb];
+        image.symbols = vec![("start".to_string(), 0x0000), ("loop".to_string(), 0x1234)];
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = encode(&sample());
+        assert!(is_container(&data));
+        let image: Image = decode(&data).unwrap();
+        assert_eq!(image.version, FORMAT_VERSION);
+        assert_eq!(image.isa, ISA_VERSION);
+        assert!(image.wide);
+        assert_eq!(image.sp, 0x70);
+        assert_eq!(image.entry, 0x1234);
+        assert_eq!(image.rom, vec![0x01, 0x02, 0x00, 0xff]);
+        assert_eq!(image.ram, vec![0xaa, 0xbb]);
+        assert_eq!(image.symbols, sample().symbols);
+        assert_eq!(encode(&image), data);
+    }
+
+    #[test]
+    fn empty_sections() {
+        let image: Image = decode(&encode(&new_image(Vec::new(), false))).unwrap();
+        assert!(!image.wide);
+        assert!(image.rom.is_empty() && image.ram.is_empty() && image.symbols.is_empty());
+    }
+
+    #[test]
+    fn u32_fields_are_big_endian() {
+        let mut out: Vec<u8> = Vec::new();
+        write_u32(&mut out, 0x01020304);
+        assert_eq!(out, vec![0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(read_u32(&out, 0), 0x01020304);
+    }
+
+    #[test]
+    fn corrupt_input() {
+        let data: Vec<u8> = encode(&sample());
+        assert!(decode(b"RBI").is_err());
+        assert!(decode(&data[..HEADER_SIZE - 1]).is_err());
+
+        let mut bad: Vec<u8> = data.clone();
+        bad[0] = b'X';
+        assert!(!is_container(&bad));
+        assert!(decode(&bad).is_err());
+
+        let mut bad: Vec<u8> = data.clone();
+        bad[4] = FORMAT_VERSION + 1;
+        assert!(decode(&bad).is_err());
+
+        // a section length that doesn't add up to the file size
+        let mut bad: Vec<u8> = data.clone();
+        bad[13] += 1;
+        assert!(decode(&bad).is_err());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        // the symbol table cut short after the name, then the name left unterminated
+        let mut image: Image = new_image(vec![0x00], false);
+        image.symbols = vec![("x".to_string(), 0x0001)];
+        let mut bad: Vec<u8> = encode(&image);
+        bad.truncate(bad.len() - 1);
+        bad[21] -= 1;
+        assert_eq!(decode(&bad).err().unwrap(), "Truncated symbol table");
+        bad.truncate(bad.len() - 2);
+        bad[21] -= 2;
+        assert_eq!(decode(&bad).err().unwrap(), "Unterminated symbol name");
+    }
+}
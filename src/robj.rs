@@ -0,0 +1,275 @@
+// .robj relocatable object - written by the assembler with --object, read by the linker
+//
+// offset  size  field
+//      0     4  magic "ROBJ"
+//      4     1  object format version
+//      5     1  ISA (microcode) version the module was built for
+//      6     4  code section length (big endian, like every other field)
+//     10     4  data section length
+//     14     4  export table length in bytes
+//     18     4  import table length in bytes
+//     22     4  relocation table length in bytes
+//     26        code, data, exports, imports, relocations
+//
+// exports are the name, a zero byte, the section and the offset in it (high, low).
+// imports are the name and a zero byte, relocations refer to them by position.
+// A relocation is the section and offset (high, low) of a byte holding an 8 bit address, the kind
+// of value to add to it and, for imports, the import index (high, low).
+
+#![allow(dead_code)]
+
+use crate::rbin::{read_u32, write_u32};
+
+pub const MAGIC: &[u8; 4] = b"ROBJ";
+pub const FORMAT_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 26;
+const RELOCATION_SIZE: usize = 6;
+
+pub const SECTION_CODE: u8 = 0;
+pub const SECTION_DATA: u8 = 1;
+
+pub const TARGET_CODE: u8 = 0; // start of this module's code section
+pub const TARGET_DATA: u8 = 1; // start of this module's data section
+pub const TARGET_IMPORT: u8 = 2; // address of an imported symbol
+
+pub struct Relocation {
+    pub section: u8,
+    pub offset: u16,
+    pub target: u8,
+    pub import: u16,
+}
+
+pub struct Object {
+    pub isa: u8,
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub exports: Vec<(String, u8, u16)>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+// splits a zero terminated name off the front of a table
+fn read_name(table: &[u8], i: usize) -> Result<(String, usize), String> {
+    match table[i..].iter().position(|b| *b == 0x00) {
+        Some(p) => Ok((String::from_utf8_lossy(&table[i..i + p]).to_string(), i + p + 1)),
+        None => Err("Unterminated symbol name".to_string()),
+    }
+}
+
+pub fn encode(object: &Object) -> Vec<u8> {
+    let mut exports: Vec<u8> = Vec::new();
+    for (name, section, offset) in &object.exports {
+        exports.extend_from_slice(name.as_bytes());
+        exports.push(0x00);
+        exports.push(*section);
+        exports.push((offset >> 8) as u8);
+        exports.push(*offset as u8);
+    }
+    let mut imports: Vec<u8> = Vec::new();
+    for name in &object.imports {
+        imports.extend_from_slice(name.as_bytes());
+        imports.push(0x00);
+    }
+    let mut relocations: Vec<u8> = Vec::new();
+    for r in &object.relocations {
+        relocations.push(r.section);
+        relocations.push((r.offset >> 8) as u8);
+        relocations.push(r.offset as u8);
+        relocations.push(r.target);
+        relocations.push((r.import >> 8) as u8);
+        relocations.push(r.import as u8);
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    out.push(object.isa);
+    write_u32(&mut out, object.code.len());
+    write_u32(&mut out, object.data.len());
+    write_u32(&mut out, exports.len());
+    write_u32(&mut out, imports.len());
+    write_u32(&mut out, relocations.len());
+    out.extend_from_slice(&object.code);
+    out.extend_from_slice(&object.data);
+    out.extend_from_slice(&exports);
+    out.extend_from_slice(&imports);
+    out.extend_from_slice(&relocations);
+    out
+}
+
+pub fn decode(data: &[u8]) -> Result<Object, String> {
+    if data.len() < 4 || &data[0..4] != MAGIC {
+        return Err("Missing ROBJ magic number".to_string());
+    }
+    if data.len() < HEADER_SIZE {
+        return Err("Truncated object header".to_string());
+    }
+    if data[4] != FORMAT_VERSION {
+        return Err(format!("Unsupported object version {} (expected {})", data[4], FORMAT_VERSION));
+    }
+
+    let code_len: usize = read_u32(data, 6);
+    let data_len: usize = read_u32(data, 10);
+    let export_len: usize = read_u32(data, 14);
+    let import_len: usize = read_u32(data, 18);
+    let reloc_len: usize = read_u32(data, 22);
+    let total: usize = HEADER_SIZE + code_len + data_len + export_len + import_len + reloc_len;
+    if data.len() != total {
+        return Err(format!("Object sections add up to {} bytes but the file is {}", total, data.len()));
+    }
+    if !reloc_len.is_multiple_of(RELOCATION_SIZE) {
+        return Err("Truncated relocation table".to_string());
+    }
+
+    let mut at: usize = HEADER_SIZE;
+    let mut object: Object = Object {
+        isa: data[5],
+        code: data[at..at + code_len].to_vec(),
+        data: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        relocations: Vec::new(),
+    };
+    at += code_len;
+    object.data = data[at..at + data_len].to_vec();
+    at += data_len;
+
+    let table: &[u8] = &data[at..at + export_len];
+    let mut i: usize = 0;
+    while i < table.len() {
+        let (name, end) = read_name(table, i)?;
+        if end + 2 >= table.len() {
+            return Err("Truncated export table".to_string());
+        }
+        object.exports.push((name, table[end], (table[end + 1] as u16) << 8 | table[end + 2] as u16));
+        i = end + 3;
+    }
+    at += export_len;
+
+    let table: &[u8] = &data[at..at + import_len];
+    let mut i: usize = 0;
+    while i < table.len() {
+        let (name, end) = read_name(table, i)?;
+        object.imports.push(name);
+        i = end;
+    }
+    at += import_len;
+
+    // the linker patches the byte a relocation names, so it has to be inside its section
+    for r in data[at..].chunks(RELOCATION_SIZE) {
+        let relocation: Relocation = Relocation {
+            section: r[0],
+            offset: (r[1] as u16) << 8 | r[2] as u16,
+            target: r[3],
+            import: (r[4] as u16) << 8 | r[5] as u16,
+        };
+        let length: usize = match relocation.section {
+            SECTION_CODE => object.code.len(),
+            SECTION_DATA => object.data.len(),
+            section => return Err(format!("Relocation in unknown section {}", section)),
+        };
+        if relocation.offset as usize >= length {
+            return Err(format!("Relocation offset {:#06x} is past the end of its section", relocation.offset));
+        }
+        match relocation.target {
+            TARGET_CODE | TARGET_DATA => {},
+            TARGET_IMPORT if (relocation.import as usize) < object.imports.len() => {},
+            TARGET_IMPORT => return Err(format!("Relocation refers to import {} but there are {}", relocation.import, object.imports.len())),
+            target => return Err(format!("Relocation has unknown target kind {}", target)),
+        }
+        object.relocations.push(relocation);
+    }
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relocation(section: u8, offset: u16, target: u8, import: u16) -> Relocation {
+        Relocation { section, offset, target, import }
+    }
+
+    fn sample() -> Object {
+        Object {
+            isa: 1,
+            code: vec![0x10, 0x00, 0x20, 0x05, 0x00],
+            data: vec![0x00, 0x07],
+            exports: vec![("main".to_string(), SECTION_CODE, 0x0000), ("table".to_string(), SECTION_DATA, 0x0001)],
+            imports: vec!["print".to_string(), "count".to_string()],
+            relocations: vec![
+                relocation(SECTION_CODE, 1, TARGET_IMPORT, 1),
+                relocation(SECTION_CODE, 4, TARGET_CODE, 0),
+                relocation(SECTION_DATA, 0, TARGET_DATA, 0),
+            ],
+        }
+    }
+
+    // the sample with one relocation swapped for a broken one
+    fn corrupt(r: Relocation) -> Result<Object, String> {
+        let mut object: Object = sample();
+        object.relocations[0] = r;
+        decode(&encode(&object))
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = encode(&sample());
+        let object: Object = decode(&data).unwrap();
+        assert_eq!(object.isa, 1);
+        assert_eq!(object.code, sample().code);
+        assert_eq!(object.data, sample().data);
+        assert_eq!(object.exports, sample().exports);
+        assert_eq!(object.imports, sample().imports);
+        let fields: Vec<(u8, u16, u8, u16)> = object.relocations.iter().map(|r| (r.section, r.offset, r.target, r.import)).collect();
+        assert_eq!(fields, vec![(SECTION_CODE, 1, TARGET_IMPORT, 1), (SECTION_CODE, 4, TARGET_CODE, 0), (SECTION_DATA, 0, TARGET_DATA, 0)]);
+        assert_eq!(encode(&object), data);
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let data: Vec<u8> = encode(&sample());
+        assert!(decode(b"RBIN").is_err());
+        assert!(decode(&data[..HEADER_SIZE - 1]).is_err());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        let mut bad: Vec<u8> = data.clone();
+        bad[4] = FORMAT_VERSION + 1;
+        assert!(decode(&bad).is_err());
+
+        // one byte dropped from the relocation table and its length
+        let mut bad: Vec<u8> = data.clone();
+        bad.truncate(bad.len() - 1);
+        bad[25] -= 1;
+        assert_eq!(decode(&bad).err().unwrap(), "Truncated relocation table");
+    }
+
+    #[test]
+    fn corrupt_tables() {
+        let mut object: Object = sample();
+        object.exports = Vec::new();
+        object.imports = Vec::new();
+        object.relocations = Vec::new();
+        let data: Vec<u8> = encode(&object);
+
+        // an export with no section or offset after its name
+        let mut bad: Vec<u8> = data.clone();
+        bad.extend_from_slice(b"x\0");
+        bad[17] = 2;
+        assert_eq!(decode(&bad).err().unwrap(), "Truncated export table");
+
+        let mut bad: Vec<u8> = data.clone();
+        bad.extend_from_slice(b"x");
+        bad[21] = 1;
+        assert_eq!(decode(&bad).err().unwrap(), "Unterminated symbol name");
+    }
+
+    #[test]
+    fn corrupt_relocations() {
+        assert_eq!(corrupt(relocation(SECTION_CODE, 5, TARGET_CODE, 0)).err().unwrap(), "Relocation offset 0x0005 is past the end of its section");
+        assert_eq!(corrupt(relocation(SECTION_DATA, 2, TARGET_CODE, 0)).err().unwrap(), "Relocation offset 0x0002 is past the end of its section");
+        assert_eq!(corrupt(relocation(2, 0, TARGET_CODE, 0)).err().unwrap(), "Relocation in unknown section 2");
+        assert_eq!(corrupt(relocation(SECTION_CODE, 1, TARGET_IMPORT, 2)).err().unwrap(), "Relocation refers to import 2 but there are 2");
+        assert_eq!(corrupt(relocation(SECTION_CODE, 1, 3, 0)).err().unwrap(), "Relocation has unknown target kind 3");
+    }
+}